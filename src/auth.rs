@@ -0,0 +1,358 @@
+use std::{error::Error, fmt, path::PathBuf};
+
+use base64::{Engine, engine::general_purpose::STANDARD_NO_PAD};
+use sha2::{Digest, Sha256};
+use ssh2::{KeyboardInteractivePrompt, Prompt, PublicKey, Session};
+
+/// One way of authenticating a session, tried in the order given by the profile
+#[derive(Clone, PartialEq, Eq, Hash)]
+pub enum AuthMethod {
+    /// Use whatever identities the running ssh-agent offers
+    Agent,
+    /// Offer only the one agent identity matching the selector
+    AgentIdentity(IdentitySelector),
+    /// A private key on disk, with the public half next to it or derived by libssh2
+    PrivateKey {
+        path: PathBuf,
+        passphrase: Option<String>,
+    },
+    /// Keyboard-interactive, answering every prompt with the given response
+    KeyboardInteractive(String),
+    /// Plain password authentication
+    Password(String),
+}
+
+impl AuthMethod {
+    /// Name of the method as the server advertises it in `auth_methods`
+    fn server_name(&self) -> &'static str {
+        match self {
+            AuthMethod::Agent | AuthMethod::AgentIdentity(_) | AuthMethod::PrivateKey { .. } => {
+                "publickey"
+            }
+            AuthMethod::KeyboardInteractive(_) => "keyboard-interactive",
+            AuthMethod::Password(_) => "password",
+        }
+    }
+
+    fn try_auth(&self, sess: &Session, user: &str) -> Result<(), ssh2::Error> {
+        match self {
+            AuthMethod::Agent => sess.userauth_agent(user),
+            AuthMethod::AgentIdentity(selector) => agent_userauth(sess, user, selector),
+            AuthMethod::PrivateKey { path, passphrase } => {
+                sess.userauth_pubkey_file(user, None, path, passphrase.as_deref())
+            }
+            AuthMethod::KeyboardInteractive(response) => {
+                let mut prompter = FixedResponse(response);
+                sess.userauth_keyboard_interactive(user, &mut prompter)
+            }
+            AuthMethod::Password(password) => sess.userauth_password(user, password),
+        }
+    }
+}
+
+impl fmt::Display for AuthMethod {
+    /// Never prints secrets, only which method and key file were used
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthMethod::Agent => write!(f, "agent"),
+            AuthMethod::AgentIdentity(selector) => write!(f, "agent identity {selector}"),
+            AuthMethod::PrivateKey { path, .. } => write!(f, "key {}", path.display()),
+            AuthMethod::KeyboardInteractive(_) => write!(f, "keyboard-interactive"),
+            AuthMethod::Password(_) => write!(f, "password"),
+        }
+    }
+}
+
+impl fmt::Debug for AuthMethod {
+    /// Like the derived form, with passwords and passphrases redacted
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        const REDACTED: &str = "<redacted>";
+        match self {
+            AuthMethod::Agent => f.write_str("Agent"),
+            AuthMethod::AgentIdentity(selector) => {
+                f.debug_tuple("AgentIdentity").field(selector).finish()
+            }
+            AuthMethod::PrivateKey { path, passphrase } => f
+                .debug_struct("PrivateKey")
+                .field("path", path)
+                .field("passphrase", &passphrase.as_ref().map(|_| REDACTED))
+                .finish(),
+            AuthMethod::KeyboardInteractive(_) => f
+                .debug_tuple("KeyboardInteractive")
+                .field(&REDACTED)
+                .finish(),
+            AuthMethod::Password(_) => f.debug_tuple("Password").field(&REDACTED).finish(),
+        }
+    }
+}
+
+/// Picks a single agent identity, so hosts with a low `MaxAuthTries` only ever see one key
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum IdentitySelector {
+    /// Exact match on the key comment, usually the file it was loaded from
+    Comment(String),
+    /// OpenSSH style `SHA256:...` fingerprint, the prefix is optional
+    Fingerprint(String),
+}
+
+impl IdentitySelector {
+    /// Treat `SHA256:` prefixed input as a fingerprint and anything else as a comment
+    pub fn parse(s: &str) -> Self {
+        if s.starts_with("SHA256:") {
+            IdentitySelector::Fingerprint(s.to_string())
+        } else {
+            IdentitySelector::Comment(s.to_string())
+        }
+    }
+
+    pub fn matches(&self, identity: &AgentIdentity) -> bool {
+        match self {
+            IdentitySelector::Comment(comment) => identity.comment == *comment,
+            IdentitySelector::Fingerprint(fp) => {
+                let fp = fp.strip_prefix("SHA256:").unwrap_or(fp);
+                identity.fingerprint.strip_prefix("SHA256:") == Some(fp)
+            }
+        }
+    }
+}
+
+impl fmt::Display for IdentitySelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IdentitySelector::Comment(comment) => write!(f, "{comment}"),
+            IdentitySelector::Fingerprint(fp) => write!(f, "{fp}"),
+        }
+    }
+}
+
+/// A public key held by the running ssh-agent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AgentIdentity {
+    pub comment: String,
+    pub blob: Vec<u8>,
+    pub fingerprint: String,
+}
+
+impl From<&PublicKey> for AgentIdentity {
+    fn from(key: &PublicKey) -> Self {
+        Self {
+            comment: key.comment().to_string(),
+            blob: key.blob().to_vec(),
+            fingerprint: fingerprint(key.blob()),
+        }
+    }
+}
+
+/// OpenSSH style `SHA256:<base64>` fingerprint of a public key blob
+pub fn fingerprint(blob: &[u8]) -> String {
+    format!("SHA256:{}", STANDARD_NO_PAD.encode(Sha256::digest(blob)))
+}
+
+/// List the identities the running ssh-agent holds, with their fingerprints
+pub fn agent_identities(sess: &Session) -> Result<Vec<AgentIdentity>, ssh2::Error> {
+    let mut agent = sess.agent()?;
+    agent.connect()?;
+    agent.list_identities()?;
+    let identities = agent
+        .identities()?
+        .iter()
+        .map(AgentIdentity::from)
+        .collect();
+    let _ = agent.disconnect();
+    Ok(identities)
+}
+
+/// Authenticate with exactly the agent identity the selector picks
+fn agent_userauth(
+    sess: &Session,
+    user: &str,
+    selector: &IdentitySelector,
+) -> Result<(), ssh2::Error> {
+    let mut agent = sess.agent()?;
+    agent.connect()?;
+    agent.list_identities()?;
+    let keys = agent.identities()?;
+    let key = keys
+        .iter()
+        .find(|key| selector.matches(&AgentIdentity::from(*key)));
+    let result = match key {
+        Some(key) => agent.userauth(user, key),
+        // LIBSSH2_ERROR_AUTHENTICATION_FAILED
+        None => Err(ssh2::Error::new(
+            ssh2::ErrorCode::Session(-18),
+            "no matching identity in agent",
+        )),
+    };
+    let _ = agent.disconnect();
+    result
+}
+
+/// Answers every keyboard-interactive prompt with the same response
+struct FixedResponse<'a>(&'a str);
+
+impl KeyboardInteractivePrompt for FixedResponse<'_> {
+    fn prompt<'a>(
+        &mut self,
+        _username: &str,
+        _instructions: &str,
+        prompts: &[Prompt<'a>],
+    ) -> Vec<String> {
+        prompts.iter().map(|_| self.0.to_string()).collect()
+    }
+}
+
+/// A method from the chain that did not authenticate the session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthAttempt {
+    pub method: String,
+    pub reason: String,
+}
+
+/// Returned when no method in the chain authenticated the session
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthError {
+    pub target: String,
+    pub attempts: Vec<AuthAttempt>,
+}
+
+impl fmt::Display for AuthError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.attempts.is_empty() {
+            return write!(
+                f,
+                "No authentication methods configured for {}",
+                self.target
+            );
+        }
+        write!(f, "Authentication to {} failed:", self.target)?;
+        for attempt in &self.attempts {
+            write!(f, "\n  {}: {}", attempt.method, attempt.reason)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for AuthError {}
+
+/// Run the chain in order against a handshaken session, stopping at the first success
+pub fn authenticate(
+    sess: &Session,
+    user: &str,
+    chain: &[AuthMethod],
+    target: &str,
+) -> Result<(), AuthError> {
+    // Asking for the method list may itself succeed with "none" auth
+    let offered = match sess.auth_methods(user) {
+        Ok(methods) => Some(methods.to_string()),
+        Err(_) if sess.authenticated() => return Ok(()),
+        Err(_) => None,
+    };
+
+    let mut attempts = Vec::new();
+    for method in chain {
+        if let Some(offered) = &offered
+            && !offered.split(',').any(|m| m == method.server_name())
+        {
+            attempts.push(AuthAttempt {
+                method: method.to_string(),
+                reason: format!("not offered by server (offers {offered})"),
+            });
+            continue;
+        }
+        let reason = match method.try_auth(sess, user) {
+            Ok(()) if sess.authenticated() => return Ok(()),
+            Ok(()) => String::from("server requires further authentication"),
+            Err(e) => e.message().to_string(),
+        };
+        attempts.push(AuthAttempt {
+            method: method.to_string(),
+            reason,
+        });
+    }
+    Err(AuthError {
+        target: target.to_string(),
+        attempts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_display_hides_secrets() {
+        let method = AuthMethod::Password(String::from("hunter2"));
+        assert_eq!(method.to_string(), "password");
+        let method = AuthMethod::PrivateKey {
+            path: PathBuf::from("/keys/id_ed25519"),
+            passphrase: Some(String::from("hunter2")),
+        };
+        assert_eq!(method.to_string(), "key /keys/id_ed25519");
+    }
+
+    #[test]
+    fn test_debug_hides_secrets() {
+        let profile = crate::ssh::ConnectionProfile::new("example.com", "me").auth(vec![
+            AuthMethod::Password(String::from("hunter2")),
+            AuthMethod::KeyboardInteractive(String::from("hunter2")),
+            AuthMethod::PrivateKey {
+                path: PathBuf::from("/keys/id_ed25519"),
+                passphrase: Some(String::from("hunter2")),
+            },
+        ]);
+        let debug = format!("{profile:?}");
+        assert!(!debug.contains("hunter2"), "{debug}");
+        assert!(debug.contains("/keys/id_ed25519"));
+        assert!(debug.contains(r#"Password("<redacted>")"#));
+    }
+
+    #[test]
+    fn test_fingerprint() {
+        // `ssh-keygen -lf` of this ed25519 public key blob
+        let blob = STANDARD_NO_PAD
+            .decode("AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl")
+            .unwrap();
+        assert_eq!(
+            fingerprint(&blob),
+            "SHA256:+DiY3wvvV6TuJJhbpZisF/zLDA0zPMSvHdkr4UvCOqU"
+        );
+    }
+
+    #[test]
+    fn test_identity_selector() {
+        let identity = AgentIdentity {
+            comment: String::from("/home/me/.ssh/id_ed25519"),
+            blob: Vec::new(),
+            fingerprint: String::from("SHA256:abc123"),
+        };
+        assert_eq!(
+            IdentitySelector::parse("SHA256:abc123"),
+            IdentitySelector::Fingerprint(String::from("SHA256:abc123"))
+        );
+        assert!(IdentitySelector::parse("SHA256:abc123").matches(&identity));
+        assert!(IdentitySelector::Fingerprint(String::from("abc123")).matches(&identity));
+        assert!(IdentitySelector::parse("/home/me/.ssh/id_ed25519").matches(&identity));
+        assert!(!IdentitySelector::parse("id_ed25519").matches(&identity));
+    }
+
+    #[test]
+    fn test_auth_error_lists_attempts() {
+        let err = AuthError {
+            target: String::from("me@host:22"),
+            attempts: vec![
+                AuthAttempt {
+                    method: String::from("agent"),
+                    reason: String::from("no identities"),
+                },
+                AuthAttempt {
+                    method: String::from("password"),
+                    reason: String::from("not offered by server (offers publickey)"),
+                },
+            ],
+        };
+        assert_eq!(
+            err.to_string(),
+            "Authentication to me@host:22 failed:\n  agent: no identities\n  password: not offered by server (offers publickey)"
+        );
+    }
+}
@@ -0,0 +1,196 @@
+use std::{
+    error::Error,
+    fmt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use color_eyre::Report;
+
+use crate::{auth::AuthError, known_hosts::HostKeyError};
+
+/// Why running something on a remote host failed
+#[derive(Debug)]
+pub enum RemoteError {
+    /// The host could not be reached or the SSH handshake failed
+    Connect(String),
+    /// The server rejected every configured authentication method
+    Auth(AuthError),
+    /// The server's host key is unknown or does not match
+    HostKey(HostKeyError),
+    /// The command ran past its deadline and was abandoned
+    Timeout { command: String, after: Duration },
+    /// The caller cancelled the command
+    Cancelled { command: String },
+    /// The command finished but reported failure
+    NonZeroExit {
+        command: String,
+        exit_code: i32,
+        signal: Option<String>,
+        stderr: String,
+    },
+    /// Opening, using or closing the channel failed, usually a dropped session
+    Channel { context: String, message: String },
+    /// The SFTP server refused a request, e.g. for a missing path
+    Sftp { path: PathBuf, message: String },
+    /// The command ran but printed something we could not parse
+    BadOutput { command: String, message: String },
+    /// We declined to run something that looked dangerous
+    Refused(String),
+}
+
+impl RemoteError {
+    pub fn channel(context: impl Into<String>, err: impl fmt::Display) -> Self {
+        RemoteError::Channel {
+            context: context.into(),
+            message: err.to_string(),
+        }
+    }
+
+    pub fn bad_output(command: impl Into<String>, err: impl fmt::Display) -> Self {
+        RemoteError::BadOutput {
+            command: command.into(),
+            message: err.to_string(),
+        }
+    }
+
+    /// Sort a failed SFTP request: errors the server reported are about the path,
+    /// anything else means the session is in trouble
+    pub fn sftp(path: &Path, err: ssh2::Error) -> Self {
+        match err.code() {
+            ssh2::ErrorCode::SFTP(_) => RemoteError::Sftp {
+                path: path.to_path_buf(),
+                message: err.message().to_string(),
+            },
+            ssh2::ErrorCode::Session(_) => RemoteError::channel("SFTP request failed", err),
+        }
+    }
+
+    /// Whether running the same thing on a fresh session could succeed
+    pub fn is_retryable(&self) -> bool {
+        matches!(self, RemoteError::Connect(_) | RemoteError::Channel { .. })
+    }
+}
+
+impl fmt::Display for RemoteError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RemoteError::Connect(reason) => write!(f, "Connection failed: {reason}"),
+            RemoteError::Auth(err) => write!(f, "{err}"),
+            RemoteError::HostKey(err) => write!(f, "{err}"),
+            RemoteError::Timeout { command, after } => {
+                write!(f, "Timed out after {:.1}s: {command}", after.as_secs_f64())
+            }
+            RemoteError::Cancelled { command } => write!(f, "Cancelled: {command}"),
+            RemoteError::NonZeroExit {
+                command,
+                exit_code,
+                signal,
+                stderr,
+            } => {
+                match signal {
+                    Some(signal) => write!(f, "`{command}` killed by SIG{signal}")?,
+                    None => write!(f, "`{command}` exited with {exit_code}")?,
+                }
+                match stderr.trim() {
+                    "" => Ok(()),
+                    stderr => write!(f, ": {stderr}"),
+                }
+            }
+            RemoteError::Channel { context, message } => write!(f, "{context}: {message}"),
+            RemoteError::Sftp { path, message } => write!(f, "{}: {message}", path.display()),
+            RemoteError::BadOutput { command, message } => {
+                write!(f, "Unexpected output from `{command}`: {message}")
+            }
+            RemoteError::Refused(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+impl Error for RemoteError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            RemoteError::Auth(err) => Some(err),
+            RemoteError::HostKey(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<Report> for RemoteError {
+    /// Sort an error from the connection layer into its kind, anything unrecognised is
+    /// taken to be a connection failure
+    fn from(report: Report) -> Self {
+        let report = match report.downcast::<RemoteError>() {
+            Ok(err) => return err,
+            Err(report) => report,
+        };
+        for cause in report.chain() {
+            if let Some(err) = cause.downcast_ref::<AuthError>() {
+                return RemoteError::Auth(err.clone());
+            }
+            if let Some(err) = cause.downcast_ref::<HostKeyError>() {
+                return RemoteError::HostKey(err.clone());
+            }
+        }
+        RemoteError::Connect(format!("{report:#}"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre::{WrapErr, eyre};
+
+    #[test]
+    fn test_from_report_finds_wrapped_auth_error() {
+        let report = Err::<(), _>(AuthError {
+            target: String::from("me@host:22"),
+            attempts: Vec::new(),
+        })
+        .wrap_err("Failed to open pooled session")
+        .unwrap_err();
+        assert!(matches!(RemoteError::from(report), RemoteError::Auth(_)));
+
+        let report = eyre!("Connection refused");
+        assert!(matches!(RemoteError::from(report), RemoteError::Connect(_)));
+    }
+
+    #[test]
+    fn test_non_zero_exit_display() {
+        let err = RemoteError::NonZeroExit {
+            command: String::from("ls /nope"),
+            exit_code: 2,
+            signal: None,
+            stderr: String::from("ls: cannot access '/nope'\n"),
+        };
+        assert_eq!(
+            err.to_string(),
+            "`ls /nope` exited with 2: ls: cannot access '/nope'"
+        );
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn test_bad_output_is_not_retried() {
+        let err = RemoteError::bad_output("find /data", "Invalid mode in find output");
+        assert_eq!(
+            err.to_string(),
+            "Unexpected output from `find /data`: Invalid mode in find output"
+        );
+        assert!(!err.is_retryable());
+    }
+
+    #[test]
+    fn test_sftp_error_kind() {
+        // LIBSSH2_FX_NO_SUCH_FILE
+        let missing = ssh2::Error::from_errno(ssh2::ErrorCode::SFTP(2));
+        let err = RemoteError::sftp(Path::new("/data/nope"), missing);
+        assert!(matches!(err, RemoteError::Sftp { .. }));
+        assert!(!err.is_retryable());
+
+        // LIBSSH2_ERROR_SOCKET_RECV
+        let dropped = ssh2::Error::from_errno(ssh2::ErrorCode::Session(-43));
+        assert!(RemoteError::sftp(Path::new("/data"), dropped).is_retryable());
+    }
+}
@@ -0,0 +1,464 @@
+use std::{
+    borrow::Cow,
+    fs,
+    io::{self, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{Result, eyre::eyre};
+
+use crate::{
+    error::RemoteError,
+    ls::{FileMeta, IdNames},
+    tx_ssh::{FileInfo, FileKind, RemoteFileOperations, SessionHold, mode_string},
+    walk::{DirSource, RemoteWalk, WalkError},
+};
+
+/// One entry of a local or remote tree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub path: PathBuf,
+    pub kind: FileKind,
+    pub size: u64,
+    /// Unix timestamp
+    pub modified_time: u64,
+    /// `st_mode`, file type bits included
+    pub mode: Option<u32>,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub inode: Option<u64>,
+    pub dev: Option<u64>,
+    pub nlink: Option<u64>,
+    pub symlink_target: Option<PathBuf>,
+}
+
+impl Entry {
+    /// Last path component, lossy where it is not UTF-8
+    pub fn name(&self) -> Cow<'_, str> {
+        self.path
+            .file_name()
+            .unwrap_or(self.path.as_os_str())
+            .to_string_lossy()
+    }
+}
+
+impl From<FileInfo> for Entry {
+    fn from(info: FileInfo) -> Self {
+        Entry {
+            kind: info.kind(),
+            path: info.raw_path,
+            size: info.size,
+            modified_time: info.modified_time,
+            mode: info.mode,
+            uid: info.uid,
+            gid: info.gid,
+            owner: info.owner,
+            group: info.group,
+            inode: info.inode,
+            dev: info.dev,
+            nlink: info.nlink,
+            symlink_target: info.symlink_target,
+        }
+    }
+}
+
+impl From<Entry> for FileInfo {
+    fn from(entry: Entry) -> Self {
+        FileInfo {
+            name: entry.name().into_owned(),
+            path: entry.path.to_string_lossy().into_owned(),
+            size: entry.size,
+            is_directory: entry.kind == FileKind::Directory,
+            is_symlink: entry.kind == FileKind::Symlink,
+            modified_time: entry.modified_time,
+            permissions: entry.mode.map(mode_string).unwrap_or_default(),
+            uid: entry.uid,
+            gid: entry.gid,
+            mode: entry.mode,
+            owner: entry.owner,
+            group: entry.group,
+            inode: entry.inode,
+            dev: entry.dev,
+            nlink: entry.nlink,
+            symlink_target: entry.symlink_target,
+            raw_path: entry.path,
+        }
+    }
+}
+
+/// The one mapping from local metadata, [`FileMeta::to_file_info`] goes through it too
+impl From<&FileMeta> for Entry {
+    fn from(meta: &FileMeta) -> Self {
+        Entry {
+            path: meta.path().to_path_buf(),
+            kind: meta.kind(),
+            size: meta.size(),
+            modified_time: meta.modified_time(),
+            mode: Some(meta.mode()),
+            uid: Some(meta.uid()),
+            gid: Some(meta.gid()),
+            owner: meta.owner().map(str::to_string),
+            group: meta.group().map(str::to_string),
+            inode: Some(meta.inode()),
+            dev: Some(meta.dev()),
+            nlink: Some(meta.nlink()),
+            symlink_target: meta.symlink_target().map(Path::to_path_buf),
+        }
+    }
+}
+
+/// A tree of files, local or remote, so code that browses, compares or copies is
+/// written once
+pub trait Filesystem {
+    /// Entries of `dir` with full paths, `.` and `..` excluded
+    fn list(&self, dir: &Path) -> Result<Vec<Entry>>;
+    /// `path` itself, symlinks not followed
+    fn stat(&self, path: &Path) -> Result<Entry>;
+    /// Whether `path` is a directory, following symlinks
+    fn is_dir(&self, path: &Path) -> Result<bool>;
+    /// Absolute form of `path` with every symlink resolved
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf>;
+    fn open_read(&self, path: &Path) -> Result<Box<dyn Read + '_>>;
+    /// Create `path`, or truncate it if it exists
+    fn open_write(&self, path: &Path) -> Result<Box<dyn Write + '_>>;
+    /// Create a directory and any missing parents
+    fn create_dir_all(&self, path: &Path) -> Result<()>;
+    /// Remove a file or symlink, or an empty directory
+    fn remove(&self, path: &Path) -> Result<()>;
+
+    /// Depth-first walk below `root`, which is not yielded itself
+    fn walk(&self, root: &Path) -> Walk<'_, Self>
+    where
+        Self: Sized,
+    {
+        Walk {
+            inner: RemoteWalk::new(Source(self), root),
+        }
+    }
+}
+
+impl<F: Filesystem + ?Sized> Filesystem for &F {
+    fn list(&self, dir: &Path) -> Result<Vec<Entry>> {
+        (**self).list(dir)
+    }
+
+    fn stat(&self, path: &Path) -> Result<Entry> {
+        (**self).stat(path)
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        (**self).is_dir(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        (**self).canonicalize(path)
+    }
+
+    fn open_read(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        (**self).open_read(path)
+    }
+
+    fn open_write(&self, path: &Path) -> Result<Box<dyn Write + '_>> {
+        (**self).open_write(path)
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        (**self).create_dir_all(path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        (**self).remove(path)
+    }
+}
+
+/// Walk of a [`Filesystem`], with the options of [`RemoteWalk`]
+pub struct Walk<'a, F> {
+    inner: RemoteWalk<Source<'a, F>>,
+}
+
+impl<F: Filesystem> Walk<'_, F> {
+    /// Don't yield entries deeper than `depth`, 1 lists the root only
+    pub fn max_depth(self, depth: usize) -> Self {
+        Walk {
+            inner: self.inner.max_depth(depth),
+        }
+    }
+
+    /// Stop after this many entries, see [`Walk::truncated`]
+    pub fn max_entries(self, entries: usize) -> Self {
+        Walk {
+            inner: self.inner.max_entries(entries),
+        }
+    }
+
+    /// Descend into symlinks to directories
+    pub fn follow_links(self, follow: bool) -> Self {
+        Walk {
+            inner: self.inner.follow_links(follow),
+        }
+    }
+
+    /// Whether the walk stopped at the entry limit with more of the tree unseen
+    pub fn truncated(&self) -> bool {
+        self.inner.truncated()
+    }
+}
+
+impl<F: Filesystem> Iterator for Walk<'_, F> {
+    type Item = Result<Entry, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        Some(self.inner.next()?.map(|entry| entry.info.into()))
+    }
+}
+
+/// Feeds a [`Filesystem`] to the walker, which works on [`FileInfo`]
+struct Source<'a, F>(&'a F);
+
+impl<F: Filesystem> DirSource for Source<'_, F> {
+    fn list(&self, dir: &Path) -> Result<Vec<FileInfo>> {
+        Ok(self.0.list(dir)?.into_iter().map(FileInfo::from).collect())
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        self.0.is_dir(path)
+    }
+
+    fn realpath(&self, path: &Path) -> Result<PathBuf> {
+        self.0.canonicalize(path)
+    }
+}
+
+/// The local disk
+#[derive(Debug)]
+pub struct LocalFilesystem {
+    names: IdNames,
+}
+
+impl LocalFilesystem {
+    pub fn new() -> Self {
+        LocalFilesystem {
+            names: IdNames::default(),
+        }
+    }
+}
+
+impl Default for LocalFilesystem {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Filesystem for LocalFilesystem {
+    fn list(&self, dir: &Path) -> Result<Vec<Entry>> {
+        fs::read_dir(dir)?
+            .map(|entry| Ok(Entry::from(&FileMeta::read(&entry?.path(), &self.names)?)))
+            .collect()
+    }
+
+    fn stat(&self, path: &Path) -> Result<Entry> {
+        Ok(Entry::from(&FileMeta::read(path, &self.names)?))
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        match fs::metadata(path) {
+            Ok(md) => Ok(md.is_dir()),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        Ok(fs::canonicalize(path)?)
+    }
+
+    fn open_read(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        Ok(Box::new(fs::File::open(path)?))
+    }
+
+    fn open_write(&self, path: &Path) -> Result<Box<dyn Write + '_>> {
+        Ok(Box::new(fs::File::create(path)?))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        Ok(fs::create_dir_all(path)?)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        if fs::symlink_metadata(path)?.is_dir() {
+            fs::remove_dir(path)?;
+        } else {
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+impl Filesystem for RemoteFileOperations<'_> {
+    fn list(&self, dir: &Path) -> Result<Vec<Entry>> {
+        Ok(self
+            .list_directory(dir)?
+            .into_iter()
+            .map(Entry::from)
+            .collect())
+    }
+
+    fn stat(&self, path: &Path) -> Result<Entry> {
+        Ok(self.stat_file(path)?.into())
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        self.is_directory(path)
+    }
+
+    fn canonicalize(&self, path: &Path) -> Result<PathBuf> {
+        self.realpath(path)
+    }
+
+    fn open_read(&self, path: &Path) -> Result<Box<dyn Read + '_>> {
+        Ok(Box::new(self.open_sftp(path, |sftp| sftp.open(path))?))
+    }
+
+    fn open_write(&self, path: &Path) -> Result<Box<dyn Write + '_>> {
+        Ok(Box::new(self.open_sftp(path, |sftp| sftp.create(path))?))
+    }
+
+    fn create_dir_all(&self, path: &Path) -> Result<()> {
+        RemoteFileOperations::create_dir_all(self, path)
+    }
+
+    fn remove(&self, path: &Path) -> Result<()> {
+        RemoteFileOperations::remove(self, path)
+    }
+}
+
+impl RemoteFileOperations<'_> {
+    /// Open a file over SFTP. Its session stays checked out, or claimed if fixed,
+    /// until the file is dropped, so nothing else runs on it meanwhile.
+    fn open_sftp(
+        &self,
+        path: &Path,
+        open: impl FnOnce(&ssh2::Sftp) -> Result<ssh2::File, ssh2::Error>,
+    ) -> Result<RemoteFile<'_>> {
+        if !self.capabilities()?.sftp {
+            return Err(eyre!("Opening remote files needs SFTP on the remote host"));
+        }
+        let (sftp, hold) = self.sftp_checkout()?;
+        let file = open(&sftp).map_err(|e| RemoteError::sftp(path, e))?;
+        Ok(RemoteFile { file, _hold: hold })
+    }
+}
+
+/// A file open over SFTP
+struct RemoteFile<'a> {
+    // declared first so the file closes before its session is released
+    file: ssh2::File,
+    _hold: SessionHold<'a>,
+}
+
+impl Read for RemoteFile<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.file.read(buf)
+    }
+}
+
+impl Write for RemoteFile<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::symlink;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_local_filesystem() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let root = tmp.path();
+        let local = LocalFilesystem::new();
+
+        local.create_dir_all(&root.join("runs/run_1"))?;
+        write!(local.open_write(&root.join("runs/run_1/out.bin"))?, "12345")?;
+        symlink("run_1", root.join("runs/latest"))?;
+
+        let mut contents = String::new();
+        local
+            .open_read(&root.join("runs/run_1/out.bin"))?
+            .read_to_string(&mut contents)?;
+        assert_eq!(contents, "12345");
+
+        let link = local.stat(&root.join("runs/latest"))?;
+        assert_eq!(link.kind, FileKind::Symlink);
+        assert_eq!(link.symlink_target, Some(PathBuf::from("run_1")));
+        assert!(local.is_dir(&root.join("runs/latest"))?);
+        assert!(!local.is_dir(&root.join("missing"))?);
+
+        let mut listed: Vec<String> = local
+            .list(&root.join("runs"))?
+            .iter()
+            .map(|entry| entry.name().into_owned())
+            .collect();
+        listed.sort();
+        assert_eq!(listed, ["latest", "run_1"]);
+
+        let walked = |fs: &dyn Filesystem| -> Vec<(PathBuf, u64)> {
+            // a trait object walks through the impl for references
+            let mut files: Vec<_> = Filesystem::walk(&fs, root)
+                .follow_links(true)
+                .map(|entry| entry.unwrap())
+                .filter(|entry| entry.kind == FileKind::File)
+                .map(|entry| (entry.path, entry.size))
+                .collect();
+            files.sort();
+            files
+        };
+        assert_eq!(
+            walked(&local),
+            vec![
+                (root.join("runs/latest/out.bin"), 5),
+                (root.join("runs/run_1/out.bin"), 5),
+            ]
+        );
+
+        local.remove(&root.join("runs/latest"))?;
+        local.remove(&root.join("runs/run_1/out.bin"))?;
+        local.remove(&root.join("runs/run_1"))?;
+        assert!(local.list(&root.join("runs"))?.is_empty());
+        assert!(local.remove(&root.join("runs/missing")).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_entry_file_info_roundtrip() {
+        let info = FileInfo {
+            name: String::from("out.bin"),
+            path: String::from("/data/out.bin"),
+            raw_path: PathBuf::from("/data/out.bin"),
+            size: 5,
+            modified_time: 1_700_000_000,
+            permissions: String::from("-rw-r--r--"),
+            mode: Some(0o100644),
+            uid: Some(1000),
+            owner: Some(String::from("alice")),
+            nlink: Some(1),
+            ..Default::default()
+        };
+        let entry = Entry::from(info.clone());
+        assert_eq!(entry.kind, FileKind::File);
+        assert_eq!(entry.name(), "out.bin");
+
+        let back = FileInfo::from(entry.clone());
+        assert_eq!(back.permissions, info.permissions);
+        assert_eq!(back.path, info.path);
+        assert_eq!(Entry::from(back), entry);
+    }
+}
@@ -0,0 +1,251 @@
+use std::{
+    ffi::OsStr,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, eyre},
+};
+
+use crate::{shell::RemoteCommand, tx_ssh::FileInfo};
+
+/// One record per entry: type, size, mtime, permission bits, owner, group, uid, gid,
+/// inode, device, link count, symlink target and path, each field terminated by NUL. Only the
+/// path and link target can hold arbitrary bytes and neither can contain a NUL.
+const FORMAT: &str = r"%y\0%s\0%T@\0%m\0%u\0%g\0%U\0%G\0%i\0%D\0%n\0%l\0%p\0";
+const FIELDS: usize = 13;
+
+/// `find` command listing the entries below `path` in [`FORMAT`], the children only
+/// unless `recursive`. Needs GNU find.
+pub fn command(path: &Path, recursive: bool) -> RemoteCommand {
+    let command = RemoteCommand::new("find")
+        .path(search_root(path))
+        .args(["-mindepth", "1"]);
+    let command = if recursive {
+        command
+    } else {
+        command.args(["-maxdepth", "1"])
+    };
+    command.args(["-printf", FORMAT])
+}
+
+/// `path` as [`command`] passes it to find, which is also how it prefixes every path
+/// it prints
+pub fn search_root(path: &Path) -> PathBuf {
+    // find takes any argument starting with `-` for an expression, `--` or not
+    if path.as_os_str().as_bytes().starts_with(b"-") {
+        Path::new(".").join(path)
+    } else {
+        path.to_path_buf()
+    }
+}
+
+/// Parse the output of [`command`]
+pub fn parse(output: &[u8]) -> Result<Vec<FileInfo>> {
+    let mut parser = RecordParser::default();
+    let mut entries = Vec::new();
+    parser.push(output, |info| entries.push(info))?;
+    parser.finish()?;
+    Ok(entries)
+}
+
+/// Parses [`command`] output as it arrives, so a tree of any size is never held in
+/// memory as a whole
+#[derive(Debug, Default)]
+pub struct RecordParser {
+    /// Start of a record not complete yet
+    pending: Vec<u8>,
+}
+
+impl RecordParser {
+    /// Feed the next chunk of output, handing each complete record to `on_entry`
+    pub fn push(&mut self, chunk: &[u8], mut on_entry: impl FnMut(FileInfo)) -> Result<()> {
+        self.pending.extend_from_slice(chunk);
+        let mut start = 0;
+        let mut fields = Vec::with_capacity(FIELDS);
+        let mut field_start = 0;
+        for (i, _) in self.pending.iter().enumerate().filter(|&(_, &b)| b == 0) {
+            fields.push(&self.pending[field_start..i]);
+            field_start = i + 1;
+            if fields.len() == FIELDS {
+                on_entry(parse_record(&fields)?);
+                fields.clear();
+                start = field_start;
+            }
+        }
+        self.pending.drain(..start);
+        Ok(())
+    }
+
+    /// Check the output did not stop halfway through a record
+    pub fn finish(self) -> Result<()> {
+        if self.pending.is_empty() {
+            Ok(())
+        } else {
+            Err(eyre!("find output ends in the middle of a record"))
+        }
+    }
+}
+
+/// One record, fields in [`FORMAT`] order
+fn parse_record(fields: &[&[u8]]) -> Result<FileInfo> {
+    let (kind, size, mtime, perm) = (fields[0], fields[1], fields[2], fields[3]);
+    let (owner, group, uid, gid) = (fields[4], fields[5], fields[6], fields[7]);
+    let (inode, dev, nlink) = (fields[8], fields[9], fields[10]);
+    let (target, path) = (fields[11], fields[12]);
+
+    let path = Path::new(OsStr::from_bytes(path));
+    let type_bits = match kind {
+        b"f" => 0o100000,
+        b"d" => 0o040000,
+        b"l" => 0o120000,
+        b"c" => 0o020000,
+        b"b" => 0o060000,
+        b"p" => 0o010000,
+        b"s" => 0o140000,
+        _ => 0,
+    };
+    let perm = u32::from_str_radix(text(perm)?, 8).wrap_err("Invalid mode in find output")?;
+    let mode = type_bits | perm;
+    // %T@ has a fractional part, whole seconds are enough
+    let mtime = text(mtime)?.split('.').next().unwrap_or_default();
+
+    Ok(FileInfo {
+        name: path
+            .file_name()
+            .unwrap_or(path.as_os_str())
+            .to_string_lossy()
+            .into_owned(),
+        path: path.to_string_lossy().into_owned(),
+        raw_path: path.to_path_buf(),
+        size: number(size, "size")?,
+        is_directory: kind == b"d",
+        is_symlink: kind == b"l",
+        modified_time: number(mtime.as_bytes(), "mtime")?,
+        permissions: crate::tx_ssh::mode_string(mode),
+        uid: Some(number(uid, "uid")?),
+        gid: Some(number(gid, "gid")?),
+        mode: Some(mode),
+        owner: Some(text(owner)?.to_string()),
+        group: Some(text(group)?.to_string()),
+        inode: Some(number(inode, "inode")?),
+        dev: Some(number(dev, "device")?),
+        nlink: Some(number(nlink, "link count")?),
+        symlink_target: (!target.is_empty()).then(|| PathBuf::from(OsStr::from_bytes(target))),
+    })
+}
+
+/// A field that is always ASCII
+fn text(field: &[u8]) -> Result<&str> {
+    std::str::from_utf8(field).wrap_err("Unexpected bytes in find output")
+}
+
+fn number<T: std::str::FromStr>(field: &[u8], what: &str) -> Result<T> {
+    text(field)?
+        .parse()
+        .map_err(|_| eyre!("Invalid {what} in find output"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{fs, os::unix::fs::symlink, process::Command};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_parse_records() {
+        let output = b"f\x001024\x001700000000.5000000000\x00644\x00alice\x00staff\x001000\x0050\x00131\x002049\x002\x00\x00/data/two  spaces\nand newline\x00\
+            l\x007\x001700000001.0000000000\x00777\x00alice\x00staff\x001000\x0050\x00132\x002049\x001\x00../\xff\x00/data/link\x00";
+        let files = parse(output).unwrap();
+        assert_eq!(files.len(), 2);
+
+        assert_eq!(files[0].name, "two  spaces\nand newline");
+        assert_eq!(files[0].size, 1024);
+        assert_eq!(files[0].modified_time, 1_700_000_000);
+        assert_eq!(files[0].permissions, "-rw-r--r--");
+        assert_eq!(files[0].owner.as_deref(), Some("alice"));
+        assert_eq!(files[0].gid, Some(50));
+        assert_eq!(files[0].dev, Some(2049));
+        assert_eq!(files[0].nlink, Some(2));
+        assert_eq!(files[0].symlink_target, None);
+
+        assert!(files[1].is_symlink);
+        assert_eq!(files[1].mode, Some(0o120777));
+        assert_eq!(
+            files[1].symlink_target.as_deref(),
+            Some(Path::new(OsStr::from_bytes(b"../\xff")))
+        );
+
+        assert!(parse(b"").unwrap().is_empty());
+        assert!(parse(b"f\x00").is_err());
+        assert!(parse(b"f\x001024").is_err());
+    }
+
+    #[test]
+    fn test_record_parser_across_chunks() {
+        let output = b"f\x001024\x001700000000.5\x00644\x00alice\x00staff\x001000\x0050\x00131\x002049\x001\x00\x00/data/a\x00\
+            d\x004096\x001700000001.0\x00755\x00alice\x00staff\x001000\x0050\x00132\x002049\x002\x00\x00/data/b\x00";
+        for split in [1, 7, 40, output.len() - 1] {
+            let mut parser = RecordParser::default();
+            let mut paths = Vec::new();
+            for chunk in output.chunks(split) {
+                parser
+                    .push(chunk, |info| paths.push(info.raw_path))
+                    .unwrap();
+            }
+            parser.finish().unwrap();
+            assert_eq!(paths, [Path::new("/data/a"), Path::new("/data/b")]);
+        }
+
+        let mut parser = RecordParser::default();
+        parser.push(&output[..30], |_| {}).unwrap();
+        assert!(parser.finish().is_err());
+    }
+
+    #[test]
+    fn test_command_against_local_find() {
+        let dir = TempDir::new().unwrap();
+        let name = OsStr::from_bytes(b"caf\xe9 -odd");
+        fs::create_dir(dir.path().join("sub")).unwrap();
+        fs::write(dir.path().join("sub").join(name), b"12345").unwrap();
+        symlink("sub", dir.path().join("link")).unwrap();
+
+        let run = |recursive| {
+            let output = Command::new("sh")
+                .arg("-c")
+                .arg(command(dir.path(), recursive).as_str())
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{output:?}");
+            let mut files = parse(&output.stdout).unwrap();
+            files.sort_by(|a, b| a.raw_path.cmp(&b.raw_path));
+            files
+        };
+
+        let children = run(false);
+        assert_eq!(children.len(), 2);
+        assert_eq!(children[0].symlink_target, Some(PathBuf::from("sub")));
+
+        let dash = dir.path().join("-x");
+        fs::create_dir(&dash).unwrap();
+        fs::write(dash.join("file"), b"1").unwrap();
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command(Path::new("-x"), true).as_str())
+            .current_dir(dir.path())
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        let files = parse(&output.stdout).unwrap();
+        assert_eq!(files.len(), 1);
+        assert!(files[0].raw_path.starts_with(search_root(Path::new("-x"))));
+        fs::remove_dir_all(&dash).unwrap();
+
+        let all = run(true);
+        assert_eq!(all.len(), 3);
+        assert_eq!(all[2].raw_path, dir.path().join("sub").join(name));
+        assert_eq!(all[2].size, 5);
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    env, error, fmt, fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{Result, eyre::WrapErr};
+use ssh2::{CheckResult, HostKeyType, KnownHostFileKind, Session};
+
+use crate::auth::fingerprint;
+
+/// What to do with a host key that is not in any known-hosts file
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+pub enum HostKeyCheck {
+    /// Refuse unknown keys with [`HostKeyError::Unknown`] so the caller can ask the user
+    #[default]
+    Strict,
+    /// Record unknown keys in the tool's file and carry on, mismatches are still refused
+    AcceptNew,
+    /// Skip verification entirely
+    Off,
+}
+
+/// The known-hosts files consulted during verification
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct KnownHostsFiles {
+    /// OpenSSH's file, only ever read
+    pub user: PathBuf,
+    /// Our own file, newly trusted keys are written here
+    pub tool: PathBuf,
+}
+
+impl Default for KnownHostsFiles {
+    fn default() -> Self {
+        let home = PathBuf::from(env::var("HOME").unwrap_or_default());
+        Self {
+            user: home.join(".ssh").join("known_hosts"),
+            tool: home.join(".config").join("tx-mon").join("known_hosts"),
+        }
+    }
+}
+
+/// A host key offered by a server during the handshake
+#[derive(Debug, Clone)]
+pub struct HostKey {
+    pub host: String,
+    pub port: u16,
+    pub key_type: HostKeyType,
+    pub key: Vec<u8>,
+    pub fingerprint: String,
+}
+
+impl HostKey {
+    /// The server's key from a session that has completed its handshake
+    pub fn from_session(sess: &Session, host: &str, port: u16) -> Option<Self> {
+        let (key, key_type) = sess.host_key()?;
+        Some(Self {
+            host: host.to_string(),
+            port,
+            key_type,
+            key: key.to_vec(),
+            fingerprint: fingerprint(key),
+        })
+    }
+
+    /// Host name as written in known-hosts files, `[host]:port` for non-standard ports
+    pub fn entry_name(&self) -> String {
+        if self.port == 22 {
+            self.host.clone()
+        } else {
+            format!("[{}]:{}", self.host, self.port)
+        }
+    }
+
+    pub fn key_type_name(&self) -> &'static str {
+        match self.key_type {
+            HostKeyType::Rsa => "RSA",
+            HostKeyType::Dss => "DSA",
+            HostKeyType::Ecdsa256 | HostKeyType::Ecdsa384 | HostKeyType::Ecdsa521 => "ECDSA",
+            HostKeyType::Ed25519 => "ED25519",
+            HostKeyType::Unknown => "unknown",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub enum HostKeyError {
+    /// No known-hosts file has an entry for the host yet
+    Unknown(HostKey),
+    /// The host is known under a different key
+    Mismatch(HostKey),
+    /// The server did not present a key, or the files could not be checked
+    Unverifiable(String),
+}
+
+impl fmt::Display for HostKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostKeyError::Unknown(key) => write!(
+                f,
+                "The authenticity of host {} can't be established. {} key fingerprint is {}",
+                key.entry_name(),
+                key.key_type_name(),
+                key.fingerprint
+            ),
+            HostKeyError::Mismatch(key) => write!(
+                f,
+                "WARNING: REMOTE HOST IDENTIFICATION HAS CHANGED for {}! \
+                 It offered {} key {} which does not match known_hosts. Refusing to connect",
+                key.entry_name(),
+                key.key_type_name(),
+                key.fingerprint
+            ),
+            HostKeyError::Unverifiable(reason) => {
+                write!(f, "Could not verify host key: {reason}")
+            }
+        }
+    }
+}
+
+impl error::Error for HostKeyError {}
+
+/// Check the session's host key against every known-hosts file that exists
+pub fn verify(
+    sess: &Session,
+    host: &str,
+    port: u16,
+    files: &KnownHostsFiles,
+    check: HostKeyCheck,
+) -> Result<(), HostKeyError> {
+    if check == HostKeyCheck::Off {
+        return Ok(());
+    }
+    let key = HostKey::from_session(sess, host, port)
+        .ok_or_else(|| HostKeyError::Unverifiable(String::from("server sent no host key")))?;
+
+    let mut known = sess
+        .known_hosts()
+        .map_err(|e| HostKeyError::Unverifiable(e.to_string()))?;
+    for path in [&files.user, &files.tool] {
+        if path.exists() {
+            known
+                .read_file(path, KnownHostFileKind::OpenSSH)
+                .map_err(|e| HostKeyError::Unverifiable(format!("{}: {e}", path.display())))?;
+        }
+    }
+
+    match known.check_port(host, port, &key.key) {
+        CheckResult::Match => Ok(()),
+        CheckResult::Mismatch => Err(HostKeyError::Mismatch(key)),
+        CheckResult::NotFound if check == HostKeyCheck::AcceptNew => {
+            trust(sess, &key, &files.tool).map_err(|e| HostKeyError::Unverifiable(format!("{e:#}")))
+        }
+        CheckResult::NotFound => Err(HostKeyError::Unknown(key)),
+        CheckResult::Failure => Err(HostKeyError::Unverifiable(String::from(
+            "known-hosts lookup failed",
+        ))),
+    }
+}
+
+/// Append a host key to the tool's known-hosts file, creating it if needed
+pub fn trust(sess: &Session, key: &HostKey, file: &Path) -> Result<()> {
+    let mut known = sess.known_hosts()?;
+    if file.exists() {
+        known
+            .read_file(file, KnownHostFileKind::OpenSSH)
+            .wrap_err_with(|| format!("Failed to read {}", file.display()))?;
+    } else if let Some(dir) = file.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    known.add(
+        &key.entry_name(),
+        &key.key,
+        "added by tx-mon",
+        key.key_type.into(),
+    )?;
+    known
+        .write_file(file, KnownHostFileKind::OpenSSH)
+        .wrap_err_with(|| format!("Failed to write {}", file.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base64::{Engine, engine::general_purpose::STANDARD};
+    use tempfile::TempDir;
+
+    const KEY: &str = "AAAAC3NzaC1lZDI1NTE5AAAAIOMqqnkVzrm0SdG6UOoqKLsabgH5C9okWi0dh2l9GKJl";
+
+    fn host_key(port: u16) -> HostKey {
+        let key = STANDARD.decode(KEY).unwrap();
+        HostKey {
+            host: String::from("data01"),
+            port,
+            key_type: HostKeyType::Ed25519,
+            fingerprint: fingerprint(&key),
+            key,
+        }
+    }
+
+    #[test]
+    fn test_entry_name() {
+        assert_eq!(host_key(22).entry_name(), "data01");
+        assert_eq!(host_key(2222).entry_name(), "[data01]:2222");
+    }
+
+    #[test]
+    fn test_trust_writes_checkable_entry() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let file = tmp.path().join("nested").join("known_hosts");
+        let sess = Session::new()?;
+        let key = host_key(2222);
+
+        trust(&sess, &key, &file)?;
+
+        let contents = fs::read_to_string(&file)?;
+        assert!(contents.starts_with(&format!("[data01]:2222 ssh-ed25519 {KEY}")));
+
+        let mut known = sess.known_hosts()?;
+        known.read_file(&file, KnownHostFileKind::OpenSSH)?;
+        assert!(matches!(
+            known.check_port("data01", 2222, &key.key),
+            CheckResult::Match
+        ));
+        assert!(matches!(
+            known.check_port("data01", 22, &key.key),
+            CheckResult::NotFound
+        ));
+        Ok(())
+    }
+}
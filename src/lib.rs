@@ -0,0 +1,17 @@
+pub mod auth;
+pub mod error;
+pub mod filesystem;
+pub mod find;
+pub mod known_hosts;
+pub mod ls;
+pub mod pool;
+pub mod probe;
+pub mod reconnect;
+pub mod rsync;
+pub mod shell;
+pub mod ssh;
+pub mod ssh_config;
+pub mod tunnel;
+pub mod tx_ssh;
+pub mod usage;
+pub mod walk;
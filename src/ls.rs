@@ -1,10 +1,32 @@
-use color_eyre::Result;
+use color_eyre::{Report, Result};
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::fs;
+use std::io;
+use std::mem::MaybeUninit;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
 use std::process::Command;
-use walkdir::WalkDir;
+use std::ptr;
+use std::sync::Mutex;
+use std::time::UNIX_EPOCH;
+use walkdir::{DirEntry, WalkDir};
 
+use crate::{
+    filesystem::Entry,
+    shell::RemoteCommand,
+    ssh::ConnectionProfile,
+    tx_ssh::{FileInfo, FileKind},
+    walk::WalkError,
+};
+
+/// A local tree, split by kind like [`crate::tx_ssh::Listing`]
 pub struct FileList {
     files: Vec<FileMeta>,
+    directories: Vec<FileMeta>,
+    symlinks: Vec<FileMeta>,
+    specials: Vec<FileMeta>,
+    errors: Vec<WalkError>,
     total_size: u64,
 }
 
@@ -12,35 +34,281 @@ pub struct FileMeta {
     path: PathBuf,
     size: u64,
     md5: Option<String>,
+    kind: FileKind,
+    modified_time: u64,
+    mode: u32,
+    uid: u32,
+    gid: u32,
+    owner: Option<String>,
+    group: Option<String>,
+    inode: u64,
+    dev: u64,
+    nlink: u64,
+    symlink_target: Option<PathBuf>,
 }
 
 impl FileList {
+    /// Scan everything below `dir`, symlinks not followed. Entries that cannot be
+    /// read are collected in [`FileList::errors`] and the scan carries on.
     pub fn create(dir: &Path) -> Result<Self> {
-        let mut files = Vec::new();
-        for entry in WalkDir::new(dir).into_iter().filter_map(|e| e.ok()) {
-            if entry.file_type().is_file() {
-                let md = entry.metadata()?;
-                let fm = FileMeta {
-                    path: entry.into_path(),
-                    size: md.len(),
-                    md5: None,
-                };
-                files.push(fm);
+        let names = IdNames::default();
+        let mut list = FileList {
+            files: Vec::new(),
+            directories: Vec::new(),
+            symlinks: Vec::new(),
+            specials: Vec::new(),
+            errors: Vec::new(),
+            total_size: 0,
+        };
+        for entry in WalkDir::new(dir).min_depth(1) {
+            let meta = entry
+                .map_err(|e| WalkError {
+                    path: e.path().unwrap_or(dir).to_path_buf(),
+                    depth: e.depth(),
+                    error: Report::new(e),
+                })
+                .and_then(|entry| FileMeta::from_entry(entry, &names));
+            match meta {
+                Ok(meta) => match meta.kind {
+                    FileKind::File => list.files.push(meta),
+                    FileKind::Directory => list.directories.push(meta),
+                    FileKind::Symlink => list.symlinks.push(meta),
+                    FileKind::Special => list.specials.push(meta),
+                },
+                Err(e) => list.errors.push(e),
             }
         }
-        let size = files.iter().map(|f| f.size).sum();
-        Ok(FileList {
-            files,
-            total_size: size,
+        list.total_size = list.files.iter().map(|f| f.size).sum();
+        Ok(list)
+    }
+
+    /// Regular files
+    pub fn files(&self) -> &[FileMeta] {
+        &self.files
+    }
+
+    pub fn directories(&self) -> &[FileMeta] {
+        &self.directories
+    }
+
+    pub fn symlinks(&self) -> &[FileMeta] {
+        &self.symlinks
+    }
+
+    /// Devices, FIFOs and sockets
+    pub fn specials(&self) -> &[FileMeta] {
+        &self.specials
+    }
+
+    /// Entries the scan could not read
+    pub fn errors(&self) -> &[WalkError] {
+        &self.errors
+    }
+
+    /// Bytes in regular files
+    pub fn total_size(&self) -> u64 {
+        self.total_size
+    }
+}
+
+impl FileMeta {
+    fn from_entry(entry: DirEntry, names: &IdNames) -> Result<Self, WalkError> {
+        let depth = entry.depth();
+        FileMeta::read(entry.path(), names).map_err(|e| WalkError {
+            path: entry.into_path(),
+            depth,
+            error: Report::new(e),
+        })
+    }
+
+    /// Metadata of `path` itself, symlinks not followed
+    pub(crate) fn read(path: &Path, names: &IdNames) -> io::Result<Self> {
+        let md = fs::symlink_metadata(path)?;
+        let file_type = md.file_type();
+        let kind = if file_type.is_dir() {
+            FileKind::Directory
+        } else if file_type.is_symlink() {
+            FileKind::Symlink
+        } else if file_type.is_file() {
+            FileKind::File
+        } else {
+            FileKind::Special
+        };
+        let symlink_target = match kind {
+            FileKind::Symlink => Some(fs::read_link(path)?),
+            _ => None,
+        };
+        Ok(FileMeta {
+            path: path.to_path_buf(),
+            size: md.len(),
+            md5: None,
+            kind,
+            modified_time: md
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or(0, |since| since.as_secs()),
+            mode: md.mode(),
+            uid: md.uid(),
+            gid: md.gid(),
+            owner: names.user(md.uid()),
+            group: names.group(md.gid()),
+            inode: md.ino(),
+            dev: md.dev(),
+            nlink: md.nlink(),
+            symlink_target,
         })
     }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn md5(&self) -> Option<&str> {
+        self.md5.as_deref()
+    }
+
+    pub fn kind(&self) -> FileKind {
+        self.kind
+    }
+
+    /// Unix timestamp
+    pub fn modified_time(&self) -> u64 {
+        self.modified_time
+    }
+
+    /// `st_mode`, file type bits included
+    pub fn mode(&self) -> u32 {
+        self.mode
+    }
+
+    pub fn uid(&self) -> u32 {
+        self.uid
+    }
+
+    pub fn gid(&self) -> u32 {
+        self.gid
+    }
+
+    pub fn owner(&self) -> Option<&str> {
+        self.owner.as_deref()
+    }
+
+    pub fn group(&self) -> Option<&str> {
+        self.group.as_deref()
+    }
+
+    pub fn inode(&self) -> u64 {
+        self.inode
+    }
+
+    pub fn dev(&self) -> u64 {
+        self.dev
+    }
+
+    pub fn nlink(&self) -> u64 {
+        self.nlink
+    }
+
+    pub fn symlink_target(&self) -> Option<&Path> {
+        self.symlink_target.as_deref()
+    }
+
+    /// The same facts in the form remote listings use, for comparing trees
+    pub fn to_file_info(&self) -> FileInfo {
+        Entry::from(self).into()
+    }
 }
-pub fn run_remote_ll(user: &str, remote: &str, path: &Path) {
-    let output = Command::new("sh")
-        .arg("-c")
-        .arg("ssh")
-        .arg(format!("{user}@{remote} ls -lA {}", path.to_str().unwrap()));
-    println!(":?output");
+
+/// Local user and group names by id, looked up through NSS as `ls` and the remote
+/// `find` do, so LDAP or SSSD accounts resolve too. Each id is looked up once.
+#[derive(Debug, Default)]
+pub(crate) struct IdNames {
+    users: Mutex<HashMap<u32, Option<String>>>,
+    groups: Mutex<HashMap<u32, Option<String>>>,
+}
+
+impl IdNames {
+    pub(crate) fn user(&self, uid: u32) -> Option<String> {
+        let mut users = self.users.lock().unwrap();
+        users.entry(uid).or_insert_with(|| user_name(uid)).clone()
+    }
+
+    pub(crate) fn group(&self, gid: u32) -> Option<String> {
+        let mut groups = self.groups.lock().unwrap();
+        groups.entry(gid).or_insert_with(|| group_name(gid)).clone()
+    }
+}
+
+/// Largest buffer offered to the NSS lookups, for groups with huge member lists
+const MAX_NSS_BUFFER: usize = 1 << 20;
+
+fn user_name(uid: u32) -> Option<String> {
+    let mut buf = vec![0; 1024];
+    loop {
+        let mut pwd = MaybeUninit::<libc::passwd>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: the pointers refer to live storage of the sizes given
+        let rc = unsafe {
+            libc::getpwuid_r(
+                uid,
+                pwd.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match rc {
+            libc::ERANGE if buf.len() < MAX_NSS_BUFFER => buf.resize(buf.len() * 2, 0),
+            // SAFETY: on success the name is a NUL-terminated string inside `buf`
+            0 if !result.is_null() => {
+                return Some(name_from(unsafe { CStr::from_ptr((*result).pw_name) }));
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn group_name(gid: u32) -> Option<String> {
+    let mut buf = vec![0; 1024];
+    loop {
+        let mut grp = MaybeUninit::<libc::group>::uninit();
+        let mut result = ptr::null_mut();
+        // SAFETY: the pointers refer to live storage of the sizes given
+        let rc = unsafe {
+            libc::getgrgid_r(
+                gid,
+                grp.as_mut_ptr(),
+                buf.as_mut_ptr(),
+                buf.len(),
+                &mut result,
+            )
+        };
+        match rc {
+            libc::ERANGE if buf.len() < MAX_NSS_BUFFER => buf.resize(buf.len() * 2, 0),
+            // SAFETY: on success the name is a NUL-terminated string inside `buf`
+            0 if !result.is_null() => {
+                return Some(name_from(unsafe { CStr::from_ptr((*result).gr_name) }));
+            }
+            _ => return None,
+        }
+    }
+}
+
+fn name_from(name: &CStr) -> String {
+    name.to_string_lossy().into_owned()
+}
+pub fn run_remote_ll(profile: &ConnectionProfile, path: &Path) -> Result<String> {
+    let output = Command::new("ssh")
+        .args(profile.ssh_args())
+        .arg(format!("{}@{}", profile.user, profile.host))
+        .arg(RemoteCommand::new("ls").arg("-lA").path(path).as_str())
+        .output()?;
+    Ok(String::from_utf8(output.stdout)?)
 }
 pub fn run_local_ll(dir: &Path) -> Result<String> {
     let output = Command::new("sh")
@@ -48,8 +316,6 @@ pub fn run_local_ll(dir: &Path) -> Result<String> {
         .arg("ls -lA")
         .arg(dir.to_str().unwrap())
         .output()?;
-
-    println!("{output:?}");
     Ok(String::from_utf8(output.stdout)?)
 }
 #[cfg(test)]
@@ -118,8 +384,61 @@ mod tests {
         Ok(())
     }
 
-    // You can add more tests here, e.g.:
-    // - empty directory
-    // - symlink handling (if you follow links)
-    // - checking that `md5` is computed correctly (once you implement it)
+    #[test]
+    fn create_keeps_metadata_and_other_kinds() -> Result<()> {
+        use std::os::unix::fs::{PermissionsExt, symlink};
+
+        let tmp = TempDir::new()?;
+        let root = tmp.path();
+        fs::create_dir(root.join("sub"))?;
+        fs::write(root.join("sub").join("data.bin"), b"12345")?;
+        fs::set_permissions(
+            root.join("sub").join("data.bin"),
+            fs::Permissions::from_mode(0o640),
+        )?;
+        symlink("sub/data.bin", root.join("latest"))?;
+
+        let fl = FileList::create(root)?;
+        assert_eq!(fl.total_size(), 5);
+        assert!(fl.errors().is_empty());
+        assert_eq!(fl.directories()[0].path(), root.join("sub"));
+
+        let data = &fl.files()[0];
+        assert_eq!(data.kind(), FileKind::File);
+        assert_eq!(data.mode(), 0o100640);
+        assert!(data.modified_time() > 0);
+        assert_eq!(data.nlink(), 1);
+        let info = data.to_file_info();
+        assert_eq!(info.name, "data.bin");
+        assert_eq!(info.permissions, "-rw-r-----");
+
+        let link = &fl.symlinks()[0];
+        assert_eq!(link.symlink_target(), Some(Path::new("sub/data.bin")));
+        Ok(())
+    }
+
+    #[test]
+    fn create_collects_walk_errors() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let fl = FileList::create(&tmp.path().join("missing"))?;
+        assert!(fl.files().is_empty());
+        assert_eq!(fl.errors().len(), 1);
+        assert_eq!(fl.errors()[0].path, tmp.path().join("missing"));
+        Ok(())
+    }
+
+    #[test]
+    fn id_names_match_id() -> Result<()> {
+        let id = |flag| -> Result<String> {
+            let output = Command::new("id").arg(flag).output()?;
+            Ok(String::from_utf8(output.stdout)?.trim().to_string())
+        };
+        let names = IdNames::default();
+        let uid: u32 = id("-u")?.parse()?;
+        let gid: u32 = id("-g")?.parse()?;
+        assert_eq!(names.user(uid), Some(id("-un")?));
+        assert_eq!(names.group(gid), Some(id("-gn")?));
+        assert_eq!(names.user(uid), Some(id("-un")?));
+        Ok(())
+    }
 }
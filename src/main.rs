@@ -1,7 +1,4 @@
-pub mod ls;
-pub mod rsync;
-pub mod ssh;
-pub mod tx_ssh;
+use clap::{Arg, ArgAction, Command, value_parser};
 use color_eyre::{
     Result,
     eyre::{WrapErr, bail},
@@ -16,20 +13,104 @@ use ratatui::{
     text::{Line, Text},
     widgets::{Block, Paragraph, Widget},
 };
+use ssh2::Session;
+use std::{
+    sync::mpsc::{self, Receiver},
+    time::Duration,
+};
+use tx_mon::{
+    auth::{self, AuthMethod, IdentitySelector},
+    error::RemoteError,
+    known_hosts::{self, HostKey, HostKeyError},
+    pool::ConnectionPool,
+    reconnect::ConnectionEvent,
+    ssh::ConnectionProfile,
+    ssh_config::SshConfig,
+};
 
 fn main() -> Result<()> {
     color_eyre::install()?;
+    let matches = cli().get_matches();
+    if matches.get_flag("list-identities") {
+        return print_agent_identities();
+    }
+    let profile = profile_from_args(matches)?;
     let mut terminal = ratatui::init();
-    let app_result = App::default().run(&mut terminal);
+    let app_result = App::new(profile).run(&mut terminal);
     ratatui::restore();
     app_result
 }
 
-#[derive(Debug, Default)]
+fn cli() -> Command {
+    Command::new("tx-mon")
+        .arg(
+            Arg::new("host")
+                .long("host")
+                .help("Remote host or ~/.ssh/config alias to connect to"),
+        )
+        .arg(
+            Arg::new("port")
+                .long("port")
+                .value_parser(value_parser!(u16))
+                .help("SSH port on the remote host"),
+        )
+        .arg(Arg::new("user").long("user").help("User to log in as"))
+        .arg(
+            Arg::new("identity")
+                .long("identity")
+                .help("Only offer the agent key with this comment or SHA256 fingerprint"),
+        )
+        .arg(
+            Arg::new("list-identities")
+                .long("list-identities")
+                .action(ArgAction::SetTrue)
+                .help("Print the keys held by ssh-agent and exit"),
+        )
+}
+
+fn print_agent_identities() -> Result<()> {
+    for identity in auth::agent_identities(&Session::new()?)? {
+        println!("{} {}", identity.fingerprint, identity.comment);
+    }
+    Ok(())
+}
+
+fn profile_from_args(matches: clap::ArgMatches) -> Result<ConnectionProfile> {
+    let mut profile = match matches.get_one::<String>("host") {
+        Some(host) => ConnectionProfile::from_ssh_config(host, &SshConfig::load_default()?)?,
+        None => ConnectionProfile::default(),
+    };
+    if let Some(port) = matches.get_one::<u16>("port") {
+        profile.port = *port;
+    }
+    if let Some(user) = matches.get_one::<String>("user") {
+        profile.user = user.clone();
+    }
+    if let Some(identity) = matches.get_one::<String>("identity") {
+        profile.auth = vec![AuthMethod::AgentIdentity(IdentitySelector::parse(identity))];
+    }
+    Ok(profile)
+}
+
+#[derive(Default)]
 pub struct App {
     counter: u8,
     exit: bool,
+    profile: ConnectionProfile,
+    pool: ConnectionPool,
+    /// reconnect progress reported by the pool
+    connection_events: Option<Receiver<ConnectionEvent>>,
+    status: Option<Status>,
+    /// host key waiting for the user to accept or reject it
+    pending_host_key: Option<HostKey>,
+}
+
+/// one-line message shown under the main view
+enum Status {
+    Info(String),
+    Error(String),
 }
+#[allow(dead_code)]
 enum Events {
     GainedFocus,
     LostFocus,
@@ -40,6 +121,16 @@ enum Events {
 }
 
 impl App {
+    pub fn new(profile: ConnectionProfile) -> Self {
+        let (events_tx, events_rx) = mpsc::channel();
+        Self {
+            profile,
+            pool: ConnectionPool::default().events(events_tx),
+            connection_events: Some(events_rx),
+            ..Default::default()
+        }
+    }
+
     /// runs the application's main loop until the user quits
     pub fn run(&mut self, terminal: &mut DefaultTerminal) -> Result<()> {
         while !self.exit {
@@ -53,8 +144,13 @@ impl App {
         frame.render_widget(self, frame.area());
     }
 
-    /// updates the application's state based on user input
+    /// updates the application's state based on user input and connection events
     fn handle_events(&mut self) -> Result<()> {
+        self.drain_connection_events();
+        // wake up regularly so reconnect progress shows without a key press
+        if !event::poll(Duration::from_millis(250))? {
+            return Ok(());
+        }
         let _ = match event::read()? {
             // it's important to check that the event is a key press event as
             // crossterm also emits key release and repeat events on Windows.
@@ -67,8 +163,12 @@ impl App {
     }
 
     fn handle_key_event(&mut self, key_event: KeyEvent) -> Result<()> {
+        if self.pending_host_key.is_some() {
+            return self.handle_host_key_prompt(key_event);
+        }
         match key_event.code {
             KeyCode::Char('q') => self.exit(),
+            KeyCode::Char('c') => self.connect()?,
             KeyCode::Left => self.decrement_counter()?,
             KeyCode::Right => self.increment_counter()?,
             _ => {}
@@ -80,6 +180,66 @@ impl App {
         self.exit = true;
     }
 
+    /// shows the latest reconnect progress in the status line
+    fn drain_connection_events(&mut self) {
+        let Some(events) = &self.connection_events else {
+            return;
+        };
+        for event in events.try_iter() {
+            self.status = Some(match event {
+                ConnectionEvent::GaveUp { .. } => Status::Error(event.to_string()),
+                _ => Status::Info(event.to_string()),
+            });
+        }
+    }
+
+    /// answers the trust-on-first-use prompt, any key other than y/n is ignored
+    fn handle_host_key_prompt(&mut self, key_event: KeyEvent) -> Result<()> {
+        match key_event.code {
+            KeyCode::Char('y') => {
+                if let Some(key) = self.pending_host_key.take() {
+                    known_hosts::trust(&Session::new()?, &key, &self.profile.known_hosts.tool)?;
+                    self.connect()?;
+                }
+            }
+            KeyCode::Char('n') | KeyCode::Esc => {
+                self.pending_host_key = None;
+                self.status = Some(Status::Error(String::from("Host key rejected")));
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    /// opens a pooled session to the configured profile, replacing any existing one
+    fn connect(&mut self) -> Result<()> {
+        self.pool.invalidate(&self.profile);
+        match self.pool.get(&self.profile) {
+            Ok(_) => {
+                self.status = Some(Status::Info(format!("Connected to {}", self.profile)));
+                Ok(())
+            }
+            // connection problems are shown, not fatal to the app
+            Err(e) => {
+                match RemoteError::from(e) {
+                    RemoteError::HostKey(HostKeyError::Unknown(key)) => {
+                        self.pending_host_key = Some(key);
+                        self.status = None;
+                    }
+                    RemoteError::HostKey(HostKeyError::Mismatch(key)) => {
+                        self.status = Some(Status::Error(format!(
+                            "Host key for {} changed to {}, refusing to connect",
+                            key.entry_name(),
+                            key.fingerprint
+                        )));
+                    }
+                    err => self.status = Some(Status::Error(err.to_string())),
+                }
+                Ok(())
+            }
+        }
+    }
+
     fn increment_counter(&mut self) -> Result<()> {
         self.counter += 1;
         if self.counter > 2 {
@@ -110,10 +270,31 @@ impl Widget for &App {
             .title_bottom(instructions.centered())
             .border_set(border::THICK);
 
-        let counter_text = Text::from(vec![Line::from(vec![
+        let mut counter_text = Text::from(vec![Line::from(vec![
             "Value: ".into(),
             self.counter.to_string().yellow(),
         ])]);
+        if let Some(key) = &self.pending_host_key {
+            counter_text.push_line(Line::from(format!(
+                "Unknown host {} with {} key",
+                key.entry_name(),
+                key.key_type_name()
+            )));
+            counter_text.push_line(Line::from(key.fingerprint.clone().yellow()));
+            counter_text.push_line(Line::from(vec![
+                "Trust this host? ".into(),
+                "<Y>".blue().bold(),
+                "/".into(),
+                "<N>".blue().bold(),
+            ]));
+        }
+        match &self.status {
+            Some(Status::Info(msg)) => counter_text.push_line(Line::from(msg.clone())),
+            Some(Status::Error(msg)) => {
+                counter_text.push_line(Line::from(msg.clone().red().bold()))
+            }
+            None => {}
+        }
 
         Paragraph::new(counter_text)
             .centered()
@@ -168,4 +349,44 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn connection_events_update_status() {
+        let (tx, rx) = mpsc::channel();
+        let mut app = App {
+            connection_events: Some(rx),
+            ..Default::default()
+        };
+        tx.send(ConnectionEvent::GaveUp {
+            target: String::from("transfer@data01:22"),
+            attempts: 5,
+            error: String::from("Connection refused"),
+        })
+        .unwrap();
+        app.drain_connection_events();
+        assert!(matches!(app.status, Some(Status::Error(_))));
+    }
+
+    #[test]
+    fn reject_pending_host_key() -> Result<()> {
+        let mut app = App {
+            pending_host_key: Some(HostKey {
+                host: String::from("data01"),
+                port: 22,
+                key_type: ssh2::HostKeyType::Ed25519,
+                key: Vec::new(),
+                fingerprint: String::from("SHA256:abc123"),
+            }),
+            ..Default::default()
+        };
+        // other keys are swallowed while the prompt is up
+        app.handle_key_event(KeyCode::Char('q').into())?;
+        assert!(!app.exit);
+        assert!(app.pending_host_key.is_some());
+
+        app.handle_key_event(KeyCode::Char('n').into())?;
+        assert!(app.pending_host_key.is_none());
+        assert!(matches!(app.status, Some(Status::Error(_))));
+        Ok(())
+    }
 }
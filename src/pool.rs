@@ -0,0 +1,615 @@
+use std::{
+    collections::HashMap,
+    io,
+    ops::{Deref, DerefMut},
+    sync::{Arc, Condvar, Mutex, MutexGuard, mpsc::Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use color_eyre::{Report, Result, eyre::WrapErr};
+use ssh2::{Channel, Session};
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    auth::AuthError,
+    error::RemoteError,
+    known_hosts::HostKeyError,
+    probe::{self, Capabilities},
+    reconnect::{ConnectionEvent, ReconnectPolicy},
+    ssh::{self, ConnectionProfile},
+};
+
+/// How often a checkout waiting for a slot looks at its cancellation token
+const CANCEL_POLL: Duration = Duration::from_millis(50);
+
+/// Sessions shared by every listing, stat and transfer, kept per profile.
+///
+/// Each checkout has a session to itself until it is dropped, after which the
+/// session goes back to the idle list for reuse. A host never sees more than
+/// `max_channels` concurrent checkouts from us. Cloning the pool shares the same
+/// sessions.
+#[derive(Clone)]
+pub struct ConnectionPool {
+    shared: Arc<Shared>,
+    max_channels: usize,
+    /// Sessions idle for longer than this get a keepalive before being handed out
+    idle_check: Duration,
+    reconnect: ReconnectPolicy,
+    events: Option<Sender<ConnectionEvent>>,
+}
+
+struct Shared {
+    hosts: Mutex<HashMap<ConnectionProfile, Host>>,
+    /// Signalled whenever a channel slot is given back
+    released: Condvar,
+}
+
+#[derive(Default)]
+struct Host {
+    /// Connected sessions nobody has checked out, most recently used last
+    idle: Vec<IdleSession>,
+    in_use: usize,
+    /// Bumped by `invalidate` so sessions checked out before it are not reused
+    generation: u64,
+    /// Probed on first request, kept across reconnects
+    capabilities: Option<Arc<Capabilities>>,
+}
+
+struct IdleSession {
+    session: Session,
+    last_used: Instant,
+}
+
+impl ConnectionPool {
+    pub fn new(max_channels: usize) -> Self {
+        Self {
+            shared: Arc::new(Shared {
+                hosts: Mutex::new(HashMap::new()),
+                released: Condvar::new(),
+            }),
+            max_channels: max_channels.max(1),
+            idle_check: Duration::from_secs(30),
+            reconnect: ReconnectPolicy::default(),
+            events: None,
+        }
+    }
+
+    pub fn idle_check(mut self, idle_check: Duration) -> Self {
+        self.idle_check = idle_check;
+        self
+    }
+
+    pub fn reconnect_policy(mut self, policy: ReconnectPolicy) -> Self {
+        self.reconnect = policy;
+        self
+    }
+
+    /// Report reconnect attempts on this channel, e.g. for the TUI's status line
+    pub fn events(mut self, events: Sender<ConnectionEvent>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Run an idempotent operation on the profile's session, reconnecting and running it
+    /// again when it fails, as the reconnect policy allows. Only transport failures are
+    /// retried, anything else is returned straight away since retrying cannot fix it.
+    pub fn with_reconnect<T>(
+        &self,
+        profile: &ConnectionProfile,
+        mut op: impl FnMut(&Session) -> Result<T>,
+    ) -> Result<T> {
+        let target = profile.to_string();
+        let max_retries = self.reconnect.max_attempts.saturating_sub(1);
+        let mut retries = 0;
+        loop {
+            let err = match self.get(profile).and_then(|session| op(&session)) {
+                Ok(value) => {
+                    if retries > 0 {
+                        self.emit(ConnectionEvent::Reconnected { target, retries });
+                    }
+                    return Ok(value);
+                }
+                Err(e) if !is_transient(&e) => return Err(e),
+                Err(e) => e,
+            };
+            self.invalidate(profile);
+            if retries >= max_retries {
+                self.emit(ConnectionEvent::GaveUp {
+                    target,
+                    attempts: retries + 1,
+                    error: format!("{err:#}"),
+                });
+                return Err(err);
+            }
+            retries += 1;
+            let delay = self.reconnect.delay(retries);
+            self.emit(ConnectionEvent::Reconnecting {
+                target: target.clone(),
+                retry: retries,
+                max_retries,
+                delay,
+                error: format!("{err:#}"),
+            });
+            thread::sleep(delay);
+        }
+    }
+
+    fn emit(&self, event: ConnectionEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    /// Check out an idle session for the profile, or connect a new one.
+    /// Blocks while the host already has `max_channels` checkouts.
+    pub fn get(&self, profile: &ConnectionProfile) -> Result<PooledSession> {
+        self.get_within(profile, None, None)
+    }
+
+    /// Like [`ConnectionPool::get`], but gives up waiting for a slot at `deadline` or
+    /// once `cancel` fires, and connects within whatever time is left. Fails with
+    /// [`RemoteError::Timeout`] or [`RemoteError::Cancelled`].
+    pub fn get_within(
+        &self,
+        profile: &ConnectionProfile,
+        deadline: Option<Instant>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<PooledSession> {
+        let mut hosts = self.acquire_slot(profile, deadline, cancel)?;
+        let host = hosts.get_mut(profile).unwrap();
+        let generation = host.generation;
+        let mut candidates = std::mem::take(&mut host.idle);
+        drop(hosts);
+
+        // health checks and connecting happen without the lock held
+        let mut reused = None;
+        while let Some(idle) = candidates.pop() {
+            if idle.last_used.elapsed() < self.idle_check || idle.session.keepalive_send().is_ok() {
+                reused = Some(idle.session);
+                break;
+            }
+        }
+        let session = match reused {
+            Some(session) => Ok(session),
+            None => {
+                let connecting = match deadline {
+                    Some(deadline) => bounded(profile, deadline),
+                    None => profile.clone(),
+                };
+                ssh::connect(&connecting)
+                    .wrap_err_with(|| format!("Failed to open pooled session to {profile}"))
+                    .inspect(|session| {
+                        session.set_timeout(profile.session_timeout_ms());
+                        session
+                            .set_keepalive(true, self.idle_check.as_secs().try_into().unwrap_or(0))
+                    })
+            }
+        };
+
+        let mut hosts = self.lock();
+        let host = hosts.get_mut(profile).unwrap();
+        // whatever we did not need goes back, unless the pool was invalidated meanwhile
+        if host.generation == generation {
+            candidates.append(&mut host.idle);
+            host.idle = candidates;
+        }
+        match session {
+            Ok(session) => Ok(PooledSession {
+                session,
+                profile: profile.clone(),
+                generation: host.generation,
+                shared: self.shared.clone(),
+            }),
+            Err(e) => {
+                host.in_use -= 1;
+                drop(hosts);
+                self.shared.released.notify_all();
+                Err(e)
+            }
+        }
+    }
+
+    /// Open a channel on the profile's session, the slot is held until the channel drops
+    pub fn channel(&self, profile: &ConnectionProfile) -> Result<PooledChannel> {
+        let session = self.get(profile)?;
+        match session.channel_session() {
+            Ok(channel) => Ok(PooledChannel {
+                channel,
+                _session: session,
+            }),
+            Err(e) => {
+                // a session that cannot open channels is dead, start over next time
+                self.invalidate(profile);
+                Err(e).wrap_err("Failed to create SSH channel")
+            }
+        }
+    }
+
+    /// Drop the profile's sessions so the next checkout reconnects. Sessions that are
+    /// checked out right now are discarded instead of returned.
+    pub fn invalidate(&self, profile: &ConnectionProfile) {
+        if let Some(host) = self.lock().get_mut(profile) {
+            host.idle.clear();
+            host.generation += 1;
+        }
+    }
+
+    /// What the profile's host supports, probed once and then served from the cache
+    pub fn capabilities(&self, profile: &ConnectionProfile) -> Result<Arc<Capabilities>> {
+        let cached = self
+            .lock()
+            .get(profile)
+            .and_then(|host| host.capabilities.clone());
+        if let Some(capabilities) = cached {
+            return Ok(capabilities);
+        }
+        let capabilities = Arc::new(self.with_reconnect(profile, probe::probe)?);
+        self.lock().entry(profile.clone()).or_default().capabilities = Some(capabilities.clone());
+        Ok(capabilities)
+    }
+
+    /// Number of checkouts currently held against a profile
+    pub fn in_use(&self, profile: &ConnectionProfile) -> usize {
+        self.lock().get(profile).map_or(0, |host| host.in_use)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<ConnectionProfile, Host>> {
+        self.shared.hosts.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Wait for a free channel slot and take it, returning with the lock held
+    fn acquire_slot(
+        &self,
+        profile: &ConnectionProfile,
+        deadline: Option<Instant>,
+        cancel: Option<&CancellationToken>,
+    ) -> Result<MutexGuard<'_, HashMap<ConnectionProfile, Host>>, RemoteError> {
+        let started = Instant::now();
+        let waiting = || format!("waiting for a channel to {profile}");
+        let mut hosts = self.lock();
+        loop {
+            let host = hosts.entry(profile.clone()).or_default();
+            if host.in_use < self.max_channels {
+                host.in_use += 1;
+                return Ok(hosts);
+            }
+            if cancel.is_some_and(CancellationToken::is_cancelled) {
+                return Err(RemoteError::Cancelled { command: waiting() });
+            }
+            let mut wait = None;
+            if let Some(deadline) = deadline {
+                let left = deadline.saturating_duration_since(Instant::now());
+                if left.is_zero() {
+                    return Err(RemoteError::Timeout {
+                        command: waiting(),
+                        after: started.elapsed(),
+                    });
+                }
+                wait = Some(left);
+            }
+            if cancel.is_some() {
+                wait = Some(wait.map_or(CANCEL_POLL, |wait| wait.min(CANCEL_POLL)));
+            }
+            hosts = match wait {
+                Some(wait) => {
+                    self.shared
+                        .released
+                        .wait_timeout(hosts, wait)
+                        .unwrap_or_else(|e| e.into_inner())
+                        .0
+                }
+                None => self
+                    .shared
+                    .released
+                    .wait(hosts)
+                    .unwrap_or_else(|e| e.into_inner()),
+            };
+        }
+    }
+}
+
+/// The profile and its jump hosts with connect and handshake limits cut to the time
+/// left before `deadline`
+fn bounded(profile: &ConnectionProfile, deadline: Instant) -> ConnectionProfile {
+    let left = deadline
+        .saturating_duration_since(Instant::now())
+        .max(Duration::from_millis(1));
+    let mut profile = profile.clone();
+    profile.connect_timeout = profile.connect_timeout.min(left);
+    profile.session_timeout = Some(profile.session_timeout.map_or(left, |t| t.min(left)));
+    profile.jump_hosts = profile
+        .jump_hosts
+        .iter()
+        .map(|jump| bounded(jump, deadline))
+        .collect();
+    profile
+}
+
+/// Errors a fresh connection could get past: transport failures and retryable
+/// [`RemoteError`]s. Anything else, a parse error or a bug on our side included, would
+/// only happen again.
+fn is_transient(err: &Report) -> bool {
+    let fatal = err.chain().any(|e| {
+        e.is::<AuthError>()
+            || e.is::<HostKeyError>()
+            || e.downcast_ref::<RemoteError>()
+                .is_some_and(|e| !e.is_retryable())
+    });
+    !fatal
+        && err.chain().any(|e| {
+            e.is::<io::Error>()
+                || e.is::<ssh2::Error>()
+                || e.downcast_ref::<RemoteError>()
+                    .is_some_and(RemoteError::is_retryable)
+        })
+}
+
+impl Default for ConnectionPool {
+    fn default() -> Self {
+        Self::new(4)
+    }
+}
+
+/// A checked out session, returned to the pool when dropped
+pub struct PooledSession {
+    session: Session,
+    profile: ConnectionProfile,
+    generation: u64,
+    shared: Arc<Shared>,
+}
+
+impl Deref for PooledSession {
+    type Target = Session;
+
+    fn deref(&self) -> &Session {
+        &self.session
+    }
+}
+
+impl Drop for PooledSession {
+    fn drop(&mut self) {
+        let mut hosts = self.shared.hosts.lock().unwrap_or_else(|e| e.into_inner());
+        if let Some(host) = hosts.get_mut(&self.profile) {
+            host.in_use -= 1;
+            if host.generation == self.generation {
+                // undo anything the borrower changed on the session
+                self.session.set_blocking(true);
+                self.session.set_timeout(self.profile.session_timeout_ms());
+                host.idle.push(IdleSession {
+                    session: self.session.clone(),
+                    last_used: Instant::now(),
+                });
+            }
+        }
+        drop(hosts);
+        self.shared.released.notify_all();
+    }
+}
+
+/// A channel on a pooled session, holding the session's slot while it lives
+pub struct PooledChannel {
+    // declared first so the channel closes before the slot is released
+    channel: Channel,
+    _session: PooledSession,
+}
+
+impl Deref for PooledChannel {
+    type Target = Channel;
+
+    fn deref(&self) -> &Channel {
+        &self.channel
+    }
+}
+
+impl DerefMut for PooledChannel {
+    fn deref_mut(&mut self) -> &mut Channel {
+        &mut self.channel
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use color_eyre::eyre::eyre;
+    use std::thread;
+
+    /// Pool with unconnected sessions already idle, so no network is needed
+    fn seeded_pool(profile: &ConnectionProfile, max_channels: usize) -> Result<ConnectionPool> {
+        let pool = ConnectionPool::new(max_channels).idle_check(Duration::from_secs(3600));
+        let mut idle = Vec::new();
+        for _ in 0..max_channels {
+            idle.push(IdleSession {
+                session: Session::new()?,
+                last_used: Instant::now(),
+            });
+        }
+        pool.lock().insert(
+            profile.clone(),
+            Host {
+                idle,
+                ..Default::default()
+            },
+        );
+        Ok(pool)
+    }
+
+    fn idle_count(pool: &ConnectionPool, profile: &ConnectionProfile) -> usize {
+        pool.lock().get(profile).map_or(0, |host| host.idle.len())
+    }
+
+    #[test]
+    fn test_checkouts_are_exclusive_and_returned() -> Result<()> {
+        let profile = ConnectionProfile::new("data01", "transfer");
+        let pool = seeded_pool(&profile, 2)?;
+
+        let a = pool.get(&profile)?;
+        let b = pool.get(&profile)?;
+        assert_eq!(pool.in_use(&profile), 2);
+        assert_eq!(idle_count(&pool, &profile), 0);
+        drop(a);
+        assert_eq!(pool.in_use(&profile), 1);
+        assert_eq!(idle_count(&pool, &profile), 1);
+        drop(b);
+        assert_eq!(pool.in_use(&profile), 0);
+        assert_eq!(idle_count(&pool, &profile), 2);
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalidate_discards_checked_out_sessions() -> Result<()> {
+        let profile = ConnectionProfile::new("data01", "transfer");
+        let pool = seeded_pool(&profile, 2)?;
+
+        let held = pool.get(&profile)?;
+        pool.invalidate(&profile);
+        assert_eq!(idle_count(&pool, &profile), 0);
+        drop(held);
+        assert_eq!(pool.in_use(&profile), 0);
+        assert_eq!(idle_count(&pool, &profile), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_blocks_at_channel_cap() -> Result<()> {
+        let profile = ConnectionProfile::new("data01", "transfer");
+        let pool = seeded_pool(&profile, 1)?;
+
+        let held = pool.get(&profile)?;
+        let waiter = {
+            let pool = pool.clone();
+            let profile = profile.clone();
+            thread::spawn(move || pool.get(&profile).map(|_| ()))
+        };
+        thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+
+        drop(held);
+        waiter.join().unwrap()?;
+        assert_eq!(pool.in_use(&profile), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_within_gives_up_at_channel_cap() -> Result<()> {
+        let profile = ConnectionProfile::new("data01", "transfer");
+        let pool = seeded_pool(&profile, 1)?;
+        let _held = pool.get(&profile)?;
+
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let Err(err) = pool.get_within(&profile, Some(deadline), None) else {
+            panic!("checkout beyond the channel cap");
+        };
+        let err = RemoteError::from(err);
+        assert!(matches!(err, RemoteError::Timeout { .. }), "{err}");
+
+        let cancel = CancellationToken::new();
+        let waiter = {
+            let (pool, profile, cancel) = (pool.clone(), profile.clone(), cancel.clone());
+            thread::spawn(move || pool.get_within(&profile, None, Some(&cancel)).map(|_| ()))
+        };
+        cancel.cancel();
+        let err = RemoteError::from(waiter.join().unwrap().unwrap_err());
+        assert!(matches!(err, RemoteError::Cancelled { .. }), "{err}");
+        assert_eq!(pool.in_use(&profile), 1);
+        Ok(())
+    }
+
+    #[test]
+    fn test_returned_session_keeps_profile_timeout() -> Result<()> {
+        let profile = ConnectionProfile::new("data01", "transfer")
+            .session_timeout(Some(Duration::from_secs(20)));
+        let pool = seeded_pool(&profile, 1)?;
+        let session = pool.get(&profile)?;
+        session.set_timeout(5);
+        drop(session);
+        assert_eq!(pool.get(&profile)?.timeout(), 20_000);
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_reconnect_retries_then_gives_up() {
+        let profile = ConnectionProfile::new("127.0.0.1", "transfer")
+            .port(1)
+            .connect_timeout(Duration::from_millis(200));
+        let (tx, rx) = std::sync::mpsc::channel();
+        let pool = ConnectionPool::new(1)
+            .reconnect_policy(ReconnectPolicy {
+                max_attempts: 3,
+                base_delay: Duration::from_millis(1),
+                max_delay: Duration::from_millis(2),
+                jitter: 0.0,
+            })
+            .events(tx);
+
+        assert!(pool.with_reconnect(&profile, |_| Ok(())).is_err());
+        let events: Vec<ConnectionEvent> = rx.try_iter().collect();
+        assert_eq!(events.len(), 3);
+        assert!(matches!(
+            events[0],
+            ConnectionEvent::Reconnecting {
+                retry: 1,
+                max_retries: 2,
+                ..
+            }
+        ));
+        assert!(matches!(
+            events[1],
+            ConnectionEvent::Reconnecting { retry: 2, .. }
+        ));
+        assert!(matches!(
+            events[2],
+            ConnectionEvent::GaveUp { attempts: 3, .. }
+        ));
+        assert_eq!(pool.in_use(&profile), 0);
+    }
+
+    #[test]
+    fn test_with_reconnect_does_not_retry_auth_failures() -> Result<()> {
+        let profile = ConnectionProfile::new("data01", "transfer");
+        let (tx, rx) = std::sync::mpsc::channel();
+        let pool = seeded_pool(&profile, 1)?.events(tx);
+        let mut calls = 0;
+        let result: Result<()> = pool.with_reconnect(&profile, |_| {
+            calls += 1;
+            Err(AuthError {
+                target: String::from("transfer@data01:22"),
+                attempts: Vec::new(),
+            }
+            .into())
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+        assert_eq!(rx.try_iter().count(), 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_reconnect_does_not_retry_local_errors() -> Result<()> {
+        let profile = ConnectionProfile::new("data01", "transfer");
+        let pool = seeded_pool(&profile, 1)?;
+        let mut calls = 0;
+        let result: Result<()> = pool.with_reconnect(&profile, |_| {
+            calls += 1;
+            Err(eyre!("Malformed find record"))
+        });
+        assert!(result.is_err());
+        assert_eq!(calls, 1);
+
+        let dropped = Report::new(RemoteError::channel("Failed to read", "socket closed"));
+        assert!(is_transient(&dropped.wrap_err("Listing failed")));
+        let reset = Report::new(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(is_transient(&reset));
+        Ok(())
+    }
+
+    #[test]
+    fn test_failed_connect_releases_slot() {
+        let profile = ConnectionProfile::new("127.0.0.1", "transfer")
+            .port(1)
+            .connect_timeout(Duration::from_millis(200));
+        let pool = ConnectionPool::new(1);
+        assert!(pool.get(&profile).is_err());
+        assert_eq!(pool.in_use(&profile), 0);
+    }
+}
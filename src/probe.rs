@@ -0,0 +1,242 @@
+use color_eyre::Result;
+use ssh2::Session;
+
+use crate::{
+    error::RemoteError,
+    tx_ssh::{CommandOutput, execute_remote_command},
+};
+
+/// Prints one `key=value` line per finding. Every check tolerates the tool being
+/// missing, so the script as a whole always succeeds.
+const PROBE_SCRIPT: &str = r#"
+printf 'os=%s\n' "$(uname -s 2>/dev/null)"
+printf 'release=%s\n' "$(uname -r 2>/dev/null)"
+printf 'machine=%s\n' "$(uname -m 2>/dev/null)"
+if stat -c %s / >/dev/null 2>&1; then echo stat=gnu
+elif stat -f %z / >/dev/null 2>&1; then echo stat=bsd
+fi
+rsync --version 2>/dev/null | head -n 1 | sed 's/^/rsync=/'
+for tool in md5sum sha256sum xxhsum b3sum; do
+  command -v "$tool" >/dev/null 2>&1 && echo "checksum=$tool"
+done
+find / -maxdepth 0 -printf '' >/dev/null 2>&1 && echo find_printf=yes
+df -Pk . 2>/dev/null | awk 'NR == 2 { print "free_kb=" $4 }'
+true
+"#;
+
+/// The probe only runs quick built-in checks
+const PROBE_TIMEOUT_SECS: u32 = 15;
+
+/// What a remote host can do, so callers pick commands that exist there
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Capabilities {
+    /// `uname -s`, e.g. `Linux` or `FreeBSD`
+    pub os: String,
+    /// `uname -r`
+    pub release: String,
+    /// `uname -m`
+    pub machine: String,
+    pub stat: StatFlavor,
+    pub rsync: Option<RsyncVersion>,
+    /// Checksum tools on the `PATH`
+    pub checksums: Vec<ChecksumTool>,
+    /// Whether `find` understands `-printf`, i.e. is GNU find
+    pub find_printf: bool,
+    /// Free space in the login directory
+    pub free_bytes: Option<u64>,
+    pub sftp: bool,
+    /// Whether the host runs commands at all, false where only SFTP is allowed
+    pub shell: bool,
+}
+
+impl Capabilities {
+    pub fn has_checksum(&self, tool: ChecksumTool) -> bool {
+        self.checksums.contains(&tool)
+    }
+
+    /// The fastest checksum tool available
+    pub fn best_checksum(&self) -> Option<ChecksumTool> {
+        self.checksums
+            .iter()
+            .copied()
+            .max_by_key(|tool| tool.speed_rank())
+    }
+}
+
+/// Which `stat` syntax the host's coreutils take
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum StatFlavor {
+    /// `stat -c FORMAT`
+    Gnu,
+    /// `stat -f FORMAT`, BSD and macOS
+    Bsd,
+    #[default]
+    Unknown,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RsyncVersion {
+    pub version: String,
+    pub protocol: u32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum ChecksumTool {
+    Md5sum,
+    Sha256sum,
+    Xxhsum,
+    B3sum,
+}
+
+impl ChecksumTool {
+    pub fn command(&self) -> &'static str {
+        match self {
+            ChecksumTool::Md5sum => "md5sum",
+            ChecksumTool::Sha256sum => "sha256sum",
+            ChecksumTool::Xxhsum => "xxhsum",
+            ChecksumTool::B3sum => "b3sum",
+        }
+    }
+
+    fn from_command(command: &str) -> Option<Self> {
+        [
+            ChecksumTool::Md5sum,
+            ChecksumTool::Sha256sum,
+            ChecksumTool::Xxhsum,
+            ChecksumTool::B3sum,
+        ]
+        .into_iter()
+        .find(|tool| tool.command() == command)
+    }
+
+    fn speed_rank(&self) -> u8 {
+        match self {
+            ChecksumTool::Sha256sum => 0,
+            ChecksumTool::Md5sum => 1,
+            ChecksumTool::B3sum => 2,
+            ChecksumTool::Xxhsum => 3,
+        }
+    }
+}
+
+/// Find out what the host behind `session` supports
+pub fn probe(session: &Session) -> Result<Capabilities> {
+    // checked first, as a host forcing internal-sftp refuses the script. The subsystem
+    // may also be disabled even where the sftp-server binary exists.
+    let sftp = session.sftp().is_ok();
+    let output = execute_remote_command(session, PROBE_SCRIPT, Some(PROBE_TIMEOUT_SECS))
+        .and_then(|output| output.check("capability probe"));
+    Ok(from_probe_result(output, sftp)?)
+}
+
+/// A failed or refused script on a host that speaks SFTP means it has no shell tools,
+/// anything else is an error
+fn from_probe_result(
+    output: Result<CommandOutput, RemoteError>,
+    sftp: bool,
+) -> Result<Capabilities, RemoteError> {
+    let mut capabilities = match output {
+        Ok(output) => Capabilities {
+            shell: true,
+            ..parse_probe_output(&output.stdout_lossy())
+        },
+        Err(RemoteError::NonZeroExit { .. } | RemoteError::Channel { .. }) if sftp => {
+            Capabilities::default()
+        }
+        Err(err) => return Err(err),
+    };
+    capabilities.sftp = sftp;
+    Ok(capabilities)
+}
+
+fn parse_probe_output(output: &str) -> Capabilities {
+    let mut capabilities = Capabilities::default();
+    for (key, value) in output.lines().filter_map(|line| line.split_once('=')) {
+        match key {
+            "os" => capabilities.os = value.to_string(),
+            "release" => capabilities.release = value.to_string(),
+            "machine" => capabilities.machine = value.to_string(),
+            "stat" if value == "gnu" => capabilities.stat = StatFlavor::Gnu,
+            "stat" if value == "bsd" => capabilities.stat = StatFlavor::Bsd,
+            "rsync" => capabilities.rsync = parse_rsync_version(value),
+            "checksum" => capabilities
+                .checksums
+                .extend(ChecksumTool::from_command(value)),
+            "find_printf" => capabilities.find_printf = true,
+            "free_kb" => {
+                capabilities.free_bytes = value.parse::<u64>().ok().map(|kb| kb * 1024);
+            }
+            _ => {}
+        }
+    }
+    capabilities
+}
+
+/// Parse the first line of `rsync --version`: `rsync  version 3.2.7  protocol version 31`
+fn parse_rsync_version(line: &str) -> Option<RsyncVersion> {
+    let mut words = line.split_whitespace();
+    if words.next()? != "rsync" {
+        return None;
+    }
+    let version = words.by_ref().skip_while(|w| *w != "version").nth(1)?;
+    let protocol = words.skip_while(|w| *w != "protocol").nth(2)?;
+    Some(RsyncVersion {
+        version: version.to_string(),
+        protocol: protocol.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_probe_output_gnu() {
+        let output = "os=Linux\nrelease=6.1.0-18-amd64\nmachine=x86_64\nstat=gnu\n\
+            rsync=rsync  version 3.2.7  protocol version 31\n\
+            checksum=md5sum\nchecksum=sha256sum\nchecksum=b3sum\n\
+            find_printf=yes\nfree_kb=1048576\n";
+        let capabilities = parse_probe_output(output);
+        assert_eq!(capabilities.os, "Linux");
+        assert_eq!(capabilities.stat, StatFlavor::Gnu);
+        assert_eq!(
+            capabilities.rsync,
+            Some(RsyncVersion {
+                version: String::from("3.2.7"),
+                protocol: 31,
+            })
+        );
+        assert_eq!(capabilities.best_checksum(), Some(ChecksumTool::B3sum));
+        assert!(capabilities.find_printf);
+        assert_eq!(capabilities.free_bytes, Some(1 << 30));
+        assert!(!capabilities.sftp);
+    }
+
+    #[test]
+    fn test_sftp_only_host_has_no_shell() {
+        let refused = || {
+            Err(RemoteError::NonZeroExit {
+                command: String::from("capability probe"),
+                exit_code: 1,
+                signal: None,
+                stderr: String::from("This service allows sftp connections only.\n"),
+            })
+        };
+        let capabilities = from_probe_result(refused(), true).unwrap();
+        assert!(capabilities.sftp);
+        assert!(!capabilities.shell);
+        assert!(!capabilities.find_printf);
+        assert!(from_probe_result(refused(), false).is_err());
+    }
+
+    #[test]
+    fn test_parse_probe_output_bsd_without_tools() {
+        let output = "os=FreeBSD\nrelease=14.0-RELEASE\nmachine=amd64\nstat=bsd\n";
+        let capabilities = parse_probe_output(output);
+        assert_eq!(capabilities.stat, StatFlavor::Bsd);
+        assert_eq!(capabilities.rsync, None);
+        assert_eq!(capabilities.best_checksum(), None);
+        assert!(!capabilities.find_printf);
+        assert_eq!(capabilities.free_bytes, None);
+    }
+}
@@ -0,0 +1,136 @@
+use std::{
+    collections::hash_map::RandomState,
+    fmt,
+    hash::{BuildHasher, Hasher},
+    time::Duration,
+};
+
+/// How often and how patiently a dropped session is re-established
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ReconnectPolicy {
+    /// Attempts in total, including the first one, before giving up
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubled for each one after
+    pub base_delay: Duration,
+    /// Upper bound for any single delay
+    pub max_delay: Duration,
+    /// Fraction of each delay that is randomised, 0.0 for none and 1.0 for full jitter
+    pub jitter: f64,
+}
+
+impl ReconnectPolicy {
+    /// Never retry, fail on the first error
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// Delay before retry number `retry`, counting from 1
+    pub fn delay(&self, retry: u32) -> Duration {
+        let exp = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)));
+        let capped = exp.min(self.max_delay);
+        let jitter = self.jitter.clamp(0.0, 1.0);
+        // keep (1 - jitter) of the delay fixed and randomise the rest
+        capped.mul_f64(1.0 - jitter + jitter * random_unit())
+    }
+}
+
+impl Default for ReconnectPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.5,
+        }
+    }
+}
+
+/// Uniform value in `[0, 1)`, seeded from the std hasher's per-instance random keys
+fn random_unit() -> f64 {
+    let bits = RandomState::new().build_hasher().finish();
+    (bits >> 11) as f64 / (1u64 << 53) as f64
+}
+
+/// Progress of a reconnect, sent to whoever listens on the pool's event channel
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConnectionEvent {
+    /// An operation failed and will be retried after `delay`
+    Reconnecting {
+        target: String,
+        retry: u32,
+        max_retries: u32,
+        delay: Duration,
+        error: String,
+    },
+    /// The operation succeeded after at least one retry
+    Reconnected { target: String, retries: u32 },
+    /// Every attempt failed
+    GaveUp {
+        target: String,
+        attempts: u32,
+        error: String,
+    },
+}
+
+impl fmt::Display for ConnectionEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConnectionEvent::Reconnecting {
+                target,
+                retry,
+                max_retries,
+                delay,
+                error,
+            } => write!(
+                f,
+                "Lost {target} ({error}), retry {retry}/{max_retries} in {:.1}s",
+                delay.as_secs_f64()
+            ),
+            ConnectionEvent::Reconnected { target, retries } => {
+                write!(f, "Reconnected to {target} after {retries} retries")
+            }
+            ConnectionEvent::GaveUp {
+                target,
+                attempts,
+                error,
+            } => write!(f, "Gave up on {target} after {attempts} attempts: {error}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_delay_doubles_and_caps_without_jitter() {
+        let policy = ReconnectPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(500),
+            jitter: 0.0,
+        };
+        let delays: Vec<u128> = (1..=5).map(|n| policy.delay(n).as_millis()).collect();
+        assert_eq!(delays, vec![100, 200, 400, 500, 500]);
+    }
+
+    #[test]
+    fn test_delay_jitter_stays_in_range() {
+        let policy = ReconnectPolicy {
+            max_attempts: 10,
+            base_delay: Duration::from_millis(1000),
+            max_delay: Duration::from_secs(60),
+            jitter: 0.5,
+        };
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(delay >= Duration::from_millis(500), "{delay:?}");
+            assert!(delay <= Duration::from_millis(1000), "{delay:?}");
+        }
+    }
+}
@@ -1,27 +1,25 @@
 use std::{
-    io::Read,
+    ffi::OsString,
     path::{Path, PathBuf},
     process::Command,
 };
 
 use color_eyre::Result;
-use ssh2::Session;
 
-struct Transfer {
-    num_files: u32,
-    paths: Vec<PathBuf>,
-    bytes: u64,
+use crate::{shell, ssh::ConnectionProfile};
+
+pub struct Transfer {
+    pub num_files: u32,
+    pub paths: Vec<PathBuf>,
+    pub bytes: u64,
 }
 
-fn dry_run(
-    remote: String,
-    user: String,
+pub fn dry_run(
+    profile: &ConnectionProfile,
     pass: String,
     src_path: &Path,
     dest_path: &Path,
 ) -> Result<Transfer> {
-    let src_path = src_path.to_str().unwrap();
-    let dest_path = dest_path.to_str().unwrap();
     let mut rsync = Command::new("sshpass");
     rsync
         .arg("-p")
@@ -29,9 +27,7 @@ fn dry_run(
         .arg("rsync")
         .arg("--dry-run")
         .arg("-avz")
-        .arg("-e")
-        .arg(r#""ssh -p 2222""#)
-        .arg(format!("{user}@{remote}:{src_path}"))
+        .args(remote_args(profile, src_path))
         .arg(dest_path);
 
     println!("{rsync:?}");
@@ -49,17 +45,48 @@ fn dry_run(
     todo!()
 }
 
+/// `-e` with the ssh command and the `user@host:path` source. `--protect-args` keeps
+/// the remote shell from splitting or expanding the path.
+fn remote_args(profile: &ConnectionProfile, src_path: &Path) -> Vec<OsString> {
+    let ssh: Vec<String> = std::iter::once(String::from("ssh"))
+        .chain(profile.ssh_args().iter().map(shell::quote))
+        .collect();
+    let mut source = OsString::from(format!("{}@{}:", profile.user, profile.host));
+    source.push(src_path);
+    vec![
+        OsString::from("--protect-args"),
+        OsString::from("-e"),
+        OsString::from(ssh.join(" ")),
+        source,
+    ]
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::ssh::connect_local;
     use color_eyre::Result;
 
+    #[test]
+    fn test_remote_args_protect_path() {
+        let args = remote_args(
+            &ConnectionProfile::new("127.0.0.1", "secureuser").port(2222),
+            Path::new("/data/$(id) two words"),
+        );
+        assert_eq!(
+            args,
+            [
+                "--protect-args",
+                "-e",
+                "ssh -p 2222",
+                "secureuser@127.0.0.1:/data/$(id) two words",
+            ]
+        );
+    }
+
     #[test]
     fn test_dry_run() -> Result<()> {
         let _ = dry_run(
-            String::from("127.0.0.1"),
-            String::from("secureuser"),
+            &ConnectionProfile::new("127.0.0.1", "secureuser").port(2222),
             String::from("changeme"),
             Path::new("/home/secureuser/"),
             Path::new("~/junk"),
@@ -0,0 +1,339 @@
+use std::{ffi::OsStr, fmt, os::unix::ffi::OsStrExt};
+
+/// A command line for the POSIX `sh` on the remote host, with every argument quoted
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteCommand {
+    line: String,
+    options_ended: bool,
+}
+
+impl RemoteCommand {
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        Self {
+            line: quote(program),
+            options_ended: false,
+        }
+    }
+
+    /// Append one argument, quoted
+    pub fn arg(mut self, arg: impl AsRef<OsStr>) -> Self {
+        self.line.push(' ');
+        self.line.push_str(&quote(arg));
+        self
+    }
+
+    pub fn args<I, S>(self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        args.into_iter().fold(self, Self::arg)
+    }
+
+    /// Append `--` unless options were already ended. Call it before the first operand
+    /// that is not a path, such as the mode of `chmod`, because getopt on BSD or with
+    /// `POSIXLY_CORRECT` stops at the first operand and would take a later `--` for one.
+    pub fn end_options(mut self) -> Self {
+        if !self.options_ended {
+            self.line.push_str(" --");
+            self.options_ended = true;
+        }
+        self
+    }
+
+    /// Append an operand, preceded by `--` the first time so one starting with `-`
+    /// is not taken for an option
+    pub fn path(self, path: impl AsRef<OsStr>) -> Self {
+        self.end_options().arg(path)
+    }
+
+    /// Like [`RemoteCommand::path`], but a leading `~` or `~user` is expanded by the
+    /// remote shell
+    pub fn tilde_path(self, path: impl AsRef<OsStr>) -> Self {
+        let word = expanding_word(path.as_ref().as_bytes(), false);
+        self.end_options().raw(&word)
+    }
+
+    /// Append shell syntax verbatim, such as a redirection. Never pass it anything
+    /// that came from outside the program.
+    pub fn raw(mut self, syntax: &str) -> Self {
+        self.line.push(' ');
+        self.line.push_str(syntax);
+        self
+    }
+
+    /// Run `next` only if this command succeeds
+    pub fn and(self, next: RemoteCommand) -> Self {
+        let line = next.line;
+        self.raw("&&").raw(&line)
+    }
+
+    /// Run `next` only if this command fails
+    pub fn or(self, next: RemoteCommand) -> Self {
+        let line = next.line;
+        self.raw("||").raw(&line)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.line
+    }
+}
+
+impl fmt::Display for RemoteCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.line)
+    }
+}
+
+impl From<RemoteCommand> for String {
+    fn from(command: RemoteCommand) -> Self {
+        command.line
+    }
+}
+
+/// Print the path with a leading `~` or `~user` expanded by the remote shell,
+/// NUL-terminated
+pub fn expand_tilde(path: impl AsRef<OsStr>) -> RemoteCommand {
+    RemoteCommand::new("printf")
+        .arg(r"%s\0")
+        .raw(&expanding_word(path.as_ref().as_bytes(), false))
+}
+
+/// Print every existing path matching a glob pattern, NUL-terminated. `*`, `?` and
+/// bracket expressions match as in `sh`, a leading `~` is expanded, and everything
+/// else is taken literally.
+pub fn glob(pattern: impl AsRef<OsStr>) -> RemoteCommand {
+    let word = expanding_word(pattern.as_ref().as_bytes(), true);
+    RemoteCommand {
+        // a pattern matching nothing stays as it is, hence the existence test
+        line: format!(
+            r#"for f in {word}; do if [ -e "$f" ] || [ -L "$f" ]; then printf '%s\0' "$f"; fi; done"#
+        ),
+        options_ended: true,
+    }
+}
+
+/// A word the shell expands: tilde prefix and glob characters are left bare, the
+/// rest is quoted
+fn expanding_word(path: &[u8], glob: bool) -> String {
+    let mut word = String::new();
+    let mut rest = path;
+    if let Some(after) = path.strip_prefix(b"~") {
+        let end = after.iter().position(|&b| b == b'/').unwrap_or(after.len());
+        let user = &after[..end];
+        // only a plain user name is safe to leave unquoted
+        if user
+            .iter()
+            .all(|&b| b.is_ascii_alphanumeric() || b"._-".contains(&b))
+        {
+            word.push('~');
+            word.push_str(&String::from_utf8_lossy(user));
+            rest = &after[end..];
+            // the slash ending the tilde prefix must not be quoted
+            if let Some(after_slash) = rest.strip_prefix(b"/") {
+                word.push('/');
+                rest = after_slash;
+            }
+        }
+    }
+
+    let mut literal = Vec::new();
+    let mut i = 0;
+    while i < rest.len() {
+        let byte = rest[i];
+        let special = match byte {
+            b'*' | b'?' if glob => Some(1),
+            b'[' if glob => bracket_len(&rest[i..]),
+            b'\\' if glob && i + 1 < rest.len() => {
+                // an escaped character is taken literally
+                literal.push(rest[i + 1]);
+                i += 2;
+                continue;
+            }
+            _ => None,
+        };
+        match special {
+            Some(len) => {
+                if !literal.is_empty() {
+                    word.push_str(&quote(OsStr::from_bytes(&literal)));
+                    literal.clear();
+                }
+                word.push_str(&String::from_utf8_lossy(&rest[i..i + len]));
+                i += len;
+            }
+            None => {
+                literal.push(byte);
+                i += 1;
+            }
+        }
+    }
+    if !literal.is_empty() || word.is_empty() {
+        word.push_str(&quote(OsStr::from_bytes(&literal)));
+    }
+    word
+}
+
+/// Length of a bracket expression like `[0-9]` or `[!a-c_]` at the start of
+/// `pattern`, if it is one that is safe to leave unquoted
+fn bracket_len(pattern: &[u8]) -> Option<usize> {
+    let close = pattern.iter().skip(2).position(|&b| b == b']')? + 2;
+    let inner = &pattern[1..close];
+    let inner = inner.strip_prefix(b"!").unwrap_or(inner);
+    inner
+        .iter()
+        .all(|&b| b.is_ascii_alphanumeric() || b"-_.".contains(&b))
+        .then_some(close + 1)
+}
+
+/// Quote one word for POSIX `sh`.
+///
+/// Bytes that are not UTF-8 cannot travel in the command string itself, so they are
+/// produced on the remote side with `printf` octal escapes.
+pub fn quote(arg: impl AsRef<OsStr>) -> String {
+    let bytes = arg.as_ref().as_bytes();
+    if bytes.is_empty() {
+        return String::from("''");
+    }
+    if bytes.iter().all(|&b| is_safe(b)) {
+        // all ASCII, so this cannot fail
+        return String::from_utf8_lossy(bytes).into_owned();
+    }
+
+    let mut quoted = String::new();
+    for chunk in bytes.utf8_chunks() {
+        let valid = chunk.valid();
+        if !valid.is_empty() {
+            quoted.push('\'');
+            quoted.push_str(&valid.replace('\'', r"'\''"));
+            quoted.push('\'');
+        }
+        if !chunk.invalid().is_empty() {
+            quoted.push_str("\"$(printf '");
+            for byte in chunk.invalid() {
+                quoted.push_str(&format!("\\{byte:03o}"));
+            }
+            quoted.push_str("')\"");
+        }
+    }
+    quoted
+}
+
+/// Characters `sh` never treats specially, `~` and `=` excluded as they expand or
+/// assign at the start of a word
+fn is_safe(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || b"_-+.,:/@%".contains(&byte)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{ffi::OsString, os::unix::ffi::OsStringExt, process::Command};
+
+    /// Have the local `sh` parse the quoted word and echo it back byte for byte
+    fn roundtrip(arg: &OsStr) -> Vec<u8> {
+        let line = RemoteCommand::new("printf").arg("%s").arg(arg);
+        Command::new("sh")
+            .arg("-c")
+            .arg(line.as_str())
+            .output()
+            .unwrap()
+            .stdout
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(quote("plain/path-1.txt"), "plain/path-1.txt");
+        assert_eq!(quote(""), "''");
+        assert_eq!(quote("it's here"), r"'it'\''s here'");
+        assert_eq!(quote("~/data"), "'~/data'");
+        assert_eq!(
+            quote(OsStr::from_bytes(b"a\xffb")),
+            r#"'a'"$(printf '\377')"'b'"#
+        );
+    }
+
+    #[test]
+    fn test_quote_survives_sh() {
+        for arg in [
+            &b"spaces and 'quotes' and \"doubles\""[..],
+            b"$(rm -rf /) `id` $HOME; *",
+            b"new\nline\n",
+            b"\xc3\x28 broken \xff\xfe utf-8",
+            "caf\u{e9}".as_bytes(),
+        ] {
+            let arg = OsString::from_vec(arg.to_vec());
+            assert_eq!(roundtrip(&arg), arg.as_bytes());
+        }
+    }
+
+    /// Have the local `sh` run `command` with `HOME` set and split its output on NUL
+    fn run_in(dir: &std::path::Path, command: RemoteCommand) -> Vec<Vec<u8>> {
+        let output = Command::new("sh")
+            .arg("-c")
+            .arg(command.as_str())
+            .current_dir(dir)
+            .env("HOME", dir)
+            .output()
+            .unwrap();
+        assert!(output.status.success(), "{output:?}");
+        output
+            .stdout
+            .split(|&b| b == 0)
+            .filter(|part| !part.is_empty())
+            .map(<[u8]>::to_vec)
+            .collect()
+    }
+
+    #[test]
+    fn test_expanding_word() {
+        assert_eq!(expanding_word(b"~/data/run_*/", true), "~/data/run_*/");
+        assert_eq!(expanding_word(b"~ops/my dir", false), "~ops/'my dir'");
+        assert_eq!(expanding_word(b"~$(id)/x", false), r"'~$(id)/x'");
+        assert_eq!(
+            expanding_word(b"a b/[0-9]?[$(x)]\\*", true),
+            r"'a b/'[0-9]?'[$(x)]*'"
+        );
+        assert_eq!(expanding_word(b"*.txt", false), "'*.txt'");
+        assert_eq!(
+            RemoteCommand::new("ls").tilde_path("~/").as_str(),
+            "ls -- ~/"
+        );
+    }
+
+    #[test]
+    fn test_glob_and_tilde_against_sh() {
+        let dir = tempfile::TempDir::new().unwrap();
+        for name in ["run_1", "run_2", "run 3", "other"] {
+            std::fs::create_dir(dir.path().join(name)).unwrap();
+        }
+        std::fs::write(dir.path().join("run_file"), b"").unwrap();
+
+        let home = dir.path().as_os_str().as_bytes().to_vec();
+        assert_eq!(run_in(dir.path(), expand_tilde("~")), vec![home.clone()]);
+
+        let mut matched = run_in(dir.path(), glob("~/run_*/"));
+        matched.sort();
+        let expected: Vec<Vec<u8>> = ["run_1/", "run_2/"]
+            .iter()
+            .map(|name| [&home[..], b"/", name.as_bytes()].concat())
+            .collect();
+        assert_eq!(matched, expected);
+
+        assert_eq!(run_in(dir.path(), glob("run 3")), vec![b"run 3".to_vec()]);
+        assert!(run_in(dir.path(), glob("nothing_*")).is_empty());
+    }
+
+    #[test]
+    fn test_path_ends_options_once() {
+        let command = RemoteCommand::new("rsync")
+            .arg("-avun")
+            .path("-src/")
+            .path("dest dir/");
+        assert_eq!(command.as_str(), "rsync -avun -- -src/ 'dest dir/'");
+        let command = command.raw("2>/dev/null").or(RemoteCommand::new("true"));
+        assert_eq!(
+            command.to_string(),
+            "rsync -avun -- -src/ 'dest dir/' 2>/dev/null || true"
+        );
+    }
+}
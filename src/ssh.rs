@@ -1,46 +1,273 @@
 use std::{
-    env,
+    env, fmt,
     io::Read,
-    net::TcpStream,
-    path::{Path, PathBuf},
+    net::{TcpStream, ToSocketAddrs},
+    os::fd::AsRawFd,
+    path::Path,
+    time::Duration,
 };
 
-use color_eyre::Result;
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, eyre},
+};
 use ssh2::Session;
 
-fn new_session() -> Result<()> {
-    let sess = Session::new()?;
-    let mut agent = sess.agent()?;
-    for identity in agent.identities()? {
-        println!("An Identity!: {}", identity.comment());
-        let pubkey = identity.blob();
-        println!("{pubkey:?}");
+use crate::{
+    auth::{self, AuthMethod},
+    known_hosts::{self, HostKeyCheck, KnownHostsFiles},
+    shell::RemoteCommand,
+    ssh_config::SshConfig,
+    tunnel,
+    tx_ssh::{self, Listing, execute_remote_command},
+};
+
+/// Guards against `ProxyJump` entries in ssh config that refer back to each other
+const MAX_JUMP_DEPTH: usize = 8;
+
+/// Everything needed to open a session to one host. `Debug` shows the auth chain
+/// with its secrets redacted.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct ConnectionProfile {
+    pub host: String,
+    pub port: u16,
+    pub user: String,
+    /// Methods tried in order until one authenticates the session
+    pub auth: Vec<AuthMethod>,
+    /// Limit for establishing the TCP connection
+    pub connect_timeout: Duration,
+    /// Blocking timeout applied to the session after the handshake, `None` waits forever
+    pub session_timeout: Option<Duration>,
+    /// How strictly the server's host key is checked after the handshake
+    pub host_key_check: HostKeyCheck,
+    pub known_hosts: KnownHostsFiles,
+    /// Bastions to tunnel through, outermost first
+    pub jump_hosts: Vec<ConnectionProfile>,
+    /// The ssh config alias this profile was resolved from, which the OpenSSH client
+    /// is given instead of `host` so it applies the alias's own settings
+    pub alias: Option<String>,
+}
+
+impl ConnectionProfile {
+    pub fn new(host: impl Into<String>, user: impl Into<String>) -> Self {
+        Self {
+            host: host.into(),
+            port: 22,
+            user: user.into(),
+            auth: vec![AuthMethod::Agent],
+            connect_timeout: Duration::from_secs(10),
+            session_timeout: None,
+            host_key_check: HostKeyCheck::default(),
+            known_hosts: KnownHostsFiles::default(),
+            jump_hosts: Vec::new(),
+            alias: None,
+        }
+    }
+
+    /// Profile for a `~/.ssh/config` alias, falling back to the alias as the host name
+    pub fn from_ssh_config(alias: &str, config: &SshConfig) -> Result<Self> {
+        Self::resolve_alias(alias, config, 0)
+    }
+
+    fn resolve_alias(alias: &str, config: &SshConfig, depth: usize) -> Result<Self> {
+        if depth > MAX_JUMP_DEPTH {
+            return Err(eyre!("ProxyJump chain for {alias} is too deep"));
+        }
+        let host = config.resolve(alias);
+        let user = host
+            .user
+            .unwrap_or_else(|| env::var("USER").unwrap_or_default());
+        let mut profile = Self::new(host.host_name.as_deref().unwrap_or(alias), user);
+        profile.alias = Some(alias.to_string());
+        if let Some(port) = host.port {
+            profile.port = port;
+        }
+        // the agent first, then key files in the order the config lists them
+        profile.auth.extend(
+            host.identity_files
+                .into_iter()
+                .filter(|path| path.exists())
+                .map(|path| AuthMethod::PrivateKey {
+                    path,
+                    passphrase: None,
+                }),
+        );
+        let jumps = host.proxy_jump.iter().flat_map(|spec| spec.split(','));
+        for (i, jump) in jumps.enumerate() {
+            let (user, jump_alias, port) = parse_jump(jump)?;
+            let mut jump = Self::resolve_alias(jump_alias, config, depth + 1)?;
+            // as in OpenSSH, only the first hop is reached through its own bastions,
+            // every later one through the hop before it
+            if i > 0 {
+                jump.jump_hosts.clear();
+            }
+            if let Some(user) = user {
+                jump.user = user.to_string();
+            }
+            if let Some(port) = port {
+                jump.port = port;
+            }
+            profile.jump_hosts.append(&mut jump.jump_hosts);
+            profile.jump_hosts.push(jump);
+        }
+        Ok(profile)
+    }
+
+    pub fn port(mut self, port: u16) -> Self {
+        self.port = port;
+        self
+    }
+
+    pub fn auth(mut self, chain: Vec<AuthMethod>) -> Self {
+        self.auth = chain;
+        self
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = timeout;
+        self
+    }
+
+    pub fn session_timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.session_timeout = timeout;
+        self
+    }
+
+    pub fn host_key_check(mut self, check: HostKeyCheck) -> Self {
+        self.host_key_check = check;
+        self
+    }
+
+    /// `session_timeout` in the milliseconds libssh2 takes, 0 for none
+    pub(crate) fn session_timeout_ms(&self) -> u32 {
+        self.session_timeout.map_or(0, |timeout| {
+            timeout.as_millis().try_into().unwrap_or(u32::MAX)
+        })
+    }
+
+    /// Reach this host through `jump`, after any bastions already added
+    pub fn jump_via(mut self, jump: ConnectionProfile) -> Self {
+        self.jump_hosts.push(jump);
+        self
+    }
+
+    /// Port and `-J` options for the OpenSSH client, for tools like rsync that shell out to it
+    pub fn ssh_args(&self) -> Vec<String> {
+        let mut args = vec![String::from("-p"), self.port.to_string()];
+        if !self.jump_hosts.is_empty() {
+            let chain: Vec<String> = self.jump_hosts.iter().map(|j| j.jump_spec()).collect();
+            args.push(String::from("-J"));
+            args.push(chain.join(","));
+        }
+        args
+    }
+
+    /// `user@host:port` for `-J`, with the config alias in place of the host
+    fn jump_spec(&self) -> String {
+        let host = self.alias.as_deref().unwrap_or(&self.host);
+        format!("{}@{}:{}", self.user, host, self.port)
     }
-    Ok(())
 }
 
-pub fn connect_local(user: &str, password: &str, port: u32) -> Result<Session> {
-    let tcp = TcpStream::connect(format!("127.0.0.1:{port}"))?;
-    let mut sess = Session::new()?;
-    sess.set_tcp_stream(tcp);
-    sess.handshake()?;
-    sess.userauth_password(user, password)?;
-    assert!(sess.authenticated());
-    Ok(sess)
+/// Split a `[user@]host[:port]` jump spec, also accepting the `ssh://` form
+fn parse_jump(spec: &str) -> Result<(Option<&str>, &str, Option<u16>)> {
+    let spec = spec.trim();
+    let spec = spec.strip_prefix("ssh://").unwrap_or(spec);
+    let (user, rest) = match spec.rsplit_once('@') {
+        Some((user, rest)) => (Some(user), rest),
+        None => (None, spec),
+    };
+    let (host, port) = if let Some(bracketed) = rest.strip_prefix('[') {
+        // [v6::addr]:port
+        let (host, after) = bracketed
+            .split_once(']')
+            .ok_or_else(|| eyre!("Unterminated [ in jump host {spec}"))?;
+        (host, after.strip_prefix(':'))
+    } else {
+        match rest.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (rest, None),
+        }
+    };
+    let port = port
+        .map(|p| p.parse())
+        .transpose()
+        .wrap_err_with(|| format!("Invalid port in jump host {spec}"))?;
+    if host.is_empty() {
+        return Err(eyre!("Missing host in jump host {spec}"));
+    }
+    Ok((user, host, port))
+}
+
+impl Default for ConnectionProfile {
+    /// Profile for the local machine as the current user
+    fn default() -> Self {
+        let user = env::var("USER").unwrap_or_default();
+        Self::new("localhost", user)
+    }
 }
-pub fn connect_remote(user: &str, port: u32) -> Result<Session> {
-    let password = env::var("T_PW")?;
-    let tcp = TcpStream::connect(format!("127.0.0.1:{port}"))?;
+
+impl fmt::Display for ConnectionProfile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}:{}", self.user, self.host, self.port)
+    }
+}
+
+/// Open a TCP connection to the profile's host, trying each resolved address in turn
+fn dial(profile: &ConnectionProfile) -> Result<TcpStream> {
+    let addrs = (profile.host.as_str(), profile.port)
+        .to_socket_addrs()
+        .wrap_err_with(|| format!("Failed to resolve {}", profile.host))?;
+
+    let mut last_err = None;
+    for addr in addrs {
+        match TcpStream::connect_timeout(&addr, profile.connect_timeout) {
+            Ok(tcp) => return Ok(tcp),
+            Err(e) => last_err = Some(e),
+        }
+    }
+    match last_err {
+        Some(e) => Err(e).wrap_err_with(|| format!("Failed to connect to {profile}")),
+        None => Err(eyre!("{} did not resolve to any address", profile.host)),
+    }
+}
+
+/// Connect, handshake and authenticate a session for the given profile,
+/// tunnelling through each of its jump hosts in turn
+pub fn connect(profile: &ConnectionProfile) -> Result<Session> {
+    let Some((first, rest)) = profile.jump_hosts.split_first() else {
+        return establish(profile, dial(profile)?);
+    };
+    let mut via = establish(first, dial(first)?)?;
+    for hop in rest.iter().chain([profile]) {
+        let stream = tunnel::open(via, &hop.host, hop.port)
+            .wrap_err_with(|| format!("Failed to reach {hop} through its jump host"))?;
+        via = establish(hop, stream)?;
+    }
+    Ok(via)
+}
+
+/// Handshake, verify and authenticate over an already connected stream
+fn establish<S: 'static + AsRawFd>(profile: &ConnectionProfile, stream: S) -> Result<Session> {
     let mut sess = Session::new()?;
-    sess.set_tcp_stream(tcp);
-    sess.handshake()?;
-    sess.userauth_agent(user)?;
-    assert!(sess.authenticated());
-    sess.userauth_password(user, &password)?;
-    assert!(sess.authenticated());
+    sess.set_tcp_stream(stream);
+    sess.set_timeout(profile.session_timeout_ms());
+    sess.handshake()
+        .wrap_err_with(|| format!("SSH handshake with {profile} failed"))?;
+    // Verify before authenticating so credentials never reach an impostor
+    known_hosts::verify(
+        &sess,
+        &profile.host,
+        profile.port,
+        &profile.known_hosts,
+        profile.host_key_check,
+    )?;
+
+    auth::authenticate(&sess, &profile.user, &profile.auth, &profile.to_string())?;
     Ok(sess)
 }
-pub fn ssh_command(session: Session, command: &str) -> Result<String> {
+
+pub fn ssh_command(session: &Session, command: &str) -> Result<String> {
     let mut channel = session.channel_session()?;
     channel.exec(command)?;
     let mut s = String::new();
@@ -51,19 +278,20 @@ pub fn ssh_command(session: Session, command: &str) -> Result<String> {
         Err(code) => Err(code.into()),
     }
 }
-pub fn list_files(session: Session, path: &Path) -> Result<String> {
-    let file_list = ssh_command(session, &format!("ls -la {}", path.display()))?;
-    println!("{file_list}");
-    let (files, directories) = parse_ls(file_list)?;
-    todo!()
+/// List a remote directory with `ls`, sorted into files, directories, symlinks and
+/// specials. A leading `~` is expanded.
+pub fn list_files(session: &Session, path: &Path) -> Result<Listing> {
+    let command = RemoteCommand::new("env")
+        .args(["LC_ALL=C", "ls", "-la"])
+        .tilde_path(path);
+    let output =
+        execute_remote_command(session, command.as_str(), None)?.check(command.as_str())?;
+    Ok(parse_ls(&output.stdout_lossy()))
 }
-pub fn parse_ls(list: String) -> Result<(Vec<String>, Vec<String>)> {
-    let entries: Vec<Vec<String>> = list
-        .lines()
-        .map(|line| line.split(" ").map(|word| String::from(word)).collect())
-        .collect();
-    println!("{entries:?}");
-    todo!()
+
+/// Parse `ls -l` output, skipping the total line and the `.` and `..` entries
+pub fn parse_ls(list: &str) -> Listing {
+    tx_ssh::parse_ls_output(list).into_iter().collect()
 }
 
 #[cfg(test)]
@@ -72,25 +300,117 @@ mod tests {
 
     use super::*;
 
+    fn local_profile() -> ConnectionProfile {
+        ConnectionProfile::new("127.0.0.1", "secureuser")
+            .port(2222)
+            .auth(vec![AuthMethod::Password(String::from("changeme"))])
+            .host_key_check(HostKeyCheck::AcceptNew)
+    }
+
     #[test]
-    fn test_new_session() {
-        assert!(new_session().is_ok());
+    fn test_agent_identities() -> Result<()> {
+        let _ = auth::agent_identities(&Session::new()?);
+        Ok(())
+    }
+    #[test]
+    fn test_profile_display() {
+        assert_eq!(local_profile().to_string(), "secureuser@127.0.0.1:2222");
+    }
+    #[test]
+    fn test_profile_from_ssh_config() -> Result<()> {
+        let config =
+            SshConfig::parse("Host nas\n  HostName 10.1.2.3\n  Port 2222\n  User backup\n");
+        let profile = ConnectionProfile::from_ssh_config("nas", &config)?;
+        assert_eq!(profile.to_string(), "backup@10.1.2.3:2222");
+
+        let profile = ConnectionProfile::from_ssh_config("other.example.org", &config)?;
+        assert_eq!(profile.host, "other.example.org");
+        assert_eq!(profile.port, 22);
+        Ok(())
+    }
+    #[test]
+    fn test_parse_jump() -> Result<()> {
+        assert_eq!(parse_jump("bastion")?, (None, "bastion", None));
+        assert_eq!(
+            parse_jump("ops@bastion:2022")?,
+            (Some("ops"), "bastion", Some(2022))
+        );
+        assert_eq!(
+            parse_jump("ssh://ops@[fe80::1]:22")?,
+            (Some("ops"), "fe80::1", Some(22))
+        );
+        assert!(parse_jump("bastion:ssh").is_err());
+        Ok(())
+    }
+    #[test]
+    fn test_profile_proxy_jump_chain() -> Result<()> {
+        let config = SshConfig::parse(
+            "Host inner\n  ProxyJump edge,ops@mid:2022\nHost mid\n  ProxyJump gw\n  User nobody\n\
+             Host edge\n  HostName 10.0.0.1\n  ProxyJump gw\n",
+        );
+        let profile = ConnectionProfile::from_ssh_config("inner", &config)?;
+        let jumps: Vec<(&str, &str, u16)> = profile
+            .jump_hosts
+            .iter()
+            .map(|j| (j.user.as_str(), j.host.as_str(), j.port))
+            .collect();
+        let user = env::var("USER").unwrap_or_default();
+        assert_eq!(
+            jumps,
+            vec![
+                (user.as_str(), "gw", 22),
+                (user.as_str(), "10.0.0.1", 22),
+                ("ops", "mid", 2022)
+            ]
+        );
+        assert_eq!(
+            profile.ssh_args(),
+            vec![
+                String::from("-p"),
+                String::from("22"),
+                String::from("-J"),
+                format!("{user}@gw:22,{user}@edge:22,ops@mid:2022"),
+            ]
+        );
+        Ok(())
     }
     #[test]
     fn test_connect_local() {
-        let _ = connect_local("secureuser", "changeme", 2222);
+        let _ = connect(&local_profile());
     }
 
     #[test]
     fn test_ssh_command_ls() -> Result<()> {
-        let sess = connect_local("secureuser", "changeme", 2222)?;
-        let _ = ssh_command(sess, "ls");
+        let sess = connect(&local_profile())?;
+        let _ = ssh_command(&sess, "ls");
         Ok(())
     }
+    #[test]
+    fn test_parse_ls() {
+        let listing = parse_ls(
+            "total 12\n\
+             drwxr-xr-x 3 alice staff 4096 Jan  1 12:00 .\n\
+             drwxr-xr-x 9 alice staff 4096 Jan  1 12:00 ..\n\
+             -rw-r--r-- 1 alice staff 1024 Jan  1 12:00 notes.txt\n\
+             drwxr-xr-x 2 alice staff 4096 Jan  1 12:00 runs\n\
+             lrwxrwxrwx 1 alice staff    4 Jan  1 12:00 latest -> runs\n\
+             prw-r--r-- 1 alice staff    0 Jan  1 12:00 queue\n",
+        );
+        assert_eq!(listing.len(), 4);
+        assert_eq!(listing.files[0].name, "notes.txt");
+        assert_eq!(listing.directories[0].name, "runs");
+        assert_eq!(listing.symlinks[0].name, "latest");
+        assert_eq!(
+            listing.symlinks[0].symlink_target.as_deref(),
+            Some(Path::new("runs"))
+        );
+        assert_eq!(listing.specials[0].name, "queue");
+    }
+
     #[test]
     fn test_list_files() -> Result<()> {
-        let sess = connect_local("secureuser", "changeme", 2222)?;
-        let _ = list_files(sess, &PathBuf::from("~/"));
+        let sess = connect(&local_profile())?;
+        let _ = list_files(&sess, &PathBuf::from("~/"));
         Ok(())
     }
 }
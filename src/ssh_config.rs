@@ -0,0 +1,419 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use color_eyre::{
+    Result,
+    eyre::{WrapErr, eyre},
+};
+
+/// Includes nested deeper than this are treated as a loop, same limit as OpenSSH
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// The parts of an OpenSSH client config we understand: `Host` blocks and `Include`.
+/// `Match` blocks are skipped entirely.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SshConfig {
+    blocks: Vec<Block>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Block {
+    /// `None` for `Match` blocks, which never apply
+    patterns: Option<Vec<String>>,
+    /// Keywords lowercased, values with quotes removed
+    options: Vec<(String, String)>,
+}
+
+/// Values resolved for one host alias, `None` where the config is silent
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct HostConfig {
+    pub host_name: Option<String>,
+    pub port: Option<u16>,
+    pub user: Option<String>,
+    pub identity_files: Vec<PathBuf>,
+    /// Comma separated `[user@]host[:port]` list, `none` clears it
+    pub proxy_jump: Option<String>,
+}
+
+impl SshConfig {
+    /// Parse config text, `Include` directives are ignored
+    pub fn parse(text: &str) -> Self {
+        let lines = text.lines().filter_map(parse_line).collect();
+        Self::from_lines(lines)
+    }
+
+    /// Read a config file, splicing in anything it includes
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut lines = Vec::new();
+        let global = (String::from("host"), String::from("*"));
+        read_lines(path, 0, &global, &mut lines)?;
+        Ok(Self::from_lines(lines))
+    }
+
+    /// Read `~/.ssh/config`, an absent file is an empty config
+    pub fn load_default() -> Result<Self> {
+        let path = ssh_dir().join("config");
+        if path.exists() {
+            Self::load(&path)
+        } else {
+            Ok(Self::default())
+        }
+    }
+
+    fn from_lines(lines: Vec<(String, String)>) -> Self {
+        // options before the first Host apply to every host
+        let mut blocks = vec![Block {
+            patterns: Some(vec![String::from("*")]),
+            options: Vec::new(),
+        }];
+        for (keyword, value) in lines {
+            match keyword.as_str() {
+                "host" => blocks.push(Block {
+                    patterns: Some(value.split_whitespace().map(String::from).collect()),
+                    options: Vec::new(),
+                }),
+                "match" => blocks.push(Block {
+                    patterns: None,
+                    options: Vec::new(),
+                }),
+                _ => blocks.last_mut().unwrap().options.push((keyword, value)),
+            }
+        }
+        Self { blocks }
+    }
+
+    /// Look up an alias the way `ssh` would, the first value found for a keyword wins
+    pub fn resolve(&self, alias: &str) -> HostConfig {
+        let mut config = HostConfig::default();
+        let mut seen_jump = false;
+        for block in &self.blocks {
+            if !block.matches(alias) {
+                continue;
+            }
+            for (keyword, value) in &block.options {
+                match keyword.as_str() {
+                    "hostname" if config.host_name.is_none() => {
+                        config.host_name = Some(expand_tokens(value, alias, None));
+                    }
+                    "port" if config.port.is_none() => config.port = value.parse().ok(),
+                    "user" if config.user.is_none() => config.user = Some(value.clone()),
+                    "proxyjump" if !seen_jump => {
+                        seen_jump = true;
+                        if !value.eq_ignore_ascii_case("none") {
+                            config.proxy_jump = Some(value.clone());
+                        }
+                    }
+                    "identityfile" => config.identity_files.push(PathBuf::from(expand_tokens(
+                        value,
+                        alias,
+                        config.user.as_deref(),
+                    ))),
+                    _ => {}
+                }
+            }
+        }
+        config
+    }
+}
+
+impl Block {
+    /// True when a positive pattern matches and no negated one does
+    fn matches(&self, alias: &str) -> bool {
+        let Some(patterns) = &self.patterns else {
+            return false;
+        };
+        let alias = alias.to_lowercase();
+        let mut matched = false;
+        for pattern in patterns {
+            let pattern = pattern.to_lowercase();
+            match pattern.strip_prefix('!') {
+                Some(negated) if wildcard_match(negated, &alias) => return false,
+                Some(_) => {}
+                None => matched |= wildcard_match(&pattern, &alias),
+            }
+        }
+        matched
+    }
+}
+
+fn ssh_dir() -> PathBuf {
+    home_dir().join(".ssh")
+}
+
+fn home_dir() -> PathBuf {
+    PathBuf::from(env::var("HOME").unwrap_or_default())
+}
+
+/// Split `Keyword value` or `Keyword=value`, dropping blanks and comments
+fn parse_line(line: &str) -> Option<(String, String)> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let split = line.find(|c: char| c.is_whitespace() || c == '=')?;
+    let (keyword, rest) = line.split_at(split);
+    let value = rest
+        .trim_start()
+        .strip_prefix('=')
+        .unwrap_or(rest)
+        .trim()
+        .replace('"', "");
+    Some((keyword.to_lowercase(), value))
+}
+
+/// Append the lines of `path` and anything it includes. `enclosing` is the `Host` or
+/// `Match` line in effect where the file is included.
+fn read_lines(
+    path: &Path,
+    depth: usize,
+    enclosing: &(String, String),
+    lines: &mut Vec<(String, String)>,
+) -> Result<()> {
+    if depth > MAX_INCLUDE_DEPTH {
+        return Err(eyre!("Include nested too deeply at {}", path.display()));
+    }
+    let text = fs::read_to_string(path)
+        .wrap_err_with(|| format!("Failed to read ssh config {}", path.display()))?;
+    let mut current = enclosing.clone();
+    for (keyword, value) in text.lines().filter_map(parse_line) {
+        if keyword == "host" || keyword == "match" {
+            current = (keyword.clone(), value.clone());
+        }
+        if keyword != "include" {
+            lines.push((keyword, value));
+            continue;
+        }
+        let start = lines.len();
+        for pattern in value.split_whitespace() {
+            for include in expand_include(pattern)? {
+                read_lines(&include, depth + 1, &current, lines)?;
+            }
+        }
+        // blocks opened by the included files end with them, as in OpenSSH
+        if lines[start..]
+            .iter()
+            .any(|(keyword, _)| keyword == "host" || keyword == "match")
+        {
+            lines.push(current.clone());
+        }
+    }
+    Ok(())
+}
+
+/// Relative includes are taken from `~/.ssh`, wildcards are allowed in the file name
+fn expand_include(pattern: &str) -> Result<Vec<PathBuf>> {
+    let path = PathBuf::from(expand_tilde(pattern));
+    let path = if path.is_absolute() {
+        path
+    } else {
+        ssh_dir().join(path)
+    };
+    let Some(name) = path.file_name().and_then(|n| n.to_str()) else {
+        return Ok(Vec::new());
+    };
+    if !name.contains(['*', '?']) {
+        return Ok(if path.exists() {
+            vec![path]
+        } else {
+            Vec::new()
+        });
+    }
+    let Some(dir) = path.parent().filter(|dir| dir.is_dir()) else {
+        return Ok(Vec::new());
+    };
+    let mut matches: Vec<PathBuf> = fs::read_dir(dir)?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            entry
+                .file_name()
+                .to_str()
+                .is_some_and(|file| wildcard_match(name, file))
+        })
+        .map(|entry| entry.path())
+        .collect();
+    matches.sort();
+    Ok(matches)
+}
+
+fn expand_tilde(path: &str) -> String {
+    match path.strip_prefix("~/") {
+        Some(rest) => home_dir().join(rest).to_string_lossy().into_owned(),
+        None => path.to_string(),
+    }
+}
+
+/// Expand `~` and the `%d`, `%h`, `%r` and `%%` tokens
+fn expand_tokens(value: &str, host: &str, user: Option<&str>) -> String {
+    let value = expand_tilde(value);
+    let mut out = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('d') => out.push_str(&home_dir().to_string_lossy()),
+            Some('h') => out.push_str(host),
+            Some('r') => out.push_str(user.unwrap_or_default()),
+            Some('%') => out.push('%'),
+            Some(other) => {
+                out.push('%');
+                out.push(other);
+            }
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+/// Shell style matching of `*` and `?` against the whole of `text`
+pub fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    let mut backtrack = None;
+    while t < text.len() {
+        match pattern.get(p) {
+            Some('*') => {
+                backtrack = Some((p, t));
+                p += 1;
+            }
+            Some('?') => {
+                p += 1;
+                t += 1;
+            }
+            Some(c) if *c == text[t] => {
+                p += 1;
+                t += 1;
+            }
+            _ => match backtrack {
+                Some((bp, bt)) => {
+                    p = bp + 1;
+                    t = bt + 1;
+                    backtrack = Some((bp, bt + 1));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::TempDir;
+
+    const CONFIG: &str = "
+# cluster nodes
+Host data?? !data99
+    HostName %h.cluster.example.org
+    User transfer
+    IdentityFile /keys/cluster
+
+Host data01
+    Port 2200
+    User ignored
+
+Match exec \"true\"
+    User never
+
+Host *
+    Port=22
+    IdentityFile \"/keys/default\"
+";
+
+    #[test]
+    fn test_wildcard_match() {
+        assert!(wildcard_match("*", ""));
+        assert!(wildcard_match("data*", "data01"));
+        assert!(wildcard_match("d?ta0*1", "data001"));
+        assert!(wildcard_match("*.example.org", "a.b.example.org"));
+        assert!(!wildcard_match("data?", "data01"));
+        assert!(!wildcard_match("*.org", "example.com"));
+    }
+
+    #[test]
+    fn test_resolve_first_value_wins() {
+        let config = SshConfig::parse(CONFIG);
+        let host = config.resolve("data01");
+        assert_eq!(
+            host,
+            HostConfig {
+                host_name: Some(String::from("data01.cluster.example.org")),
+                port: Some(2200),
+                user: Some(String::from("transfer")),
+                identity_files: vec![
+                    PathBuf::from("/keys/cluster"),
+                    PathBuf::from("/keys/default")
+                ],
+                proxy_jump: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_resolve_negation_and_match_skipped() {
+        let config = SshConfig::parse(CONFIG);
+        let host = config.resolve("data99");
+        assert_eq!(host.host_name, None);
+        assert_eq!(host.port, Some(22));
+        assert_eq!(host.user, None);
+        assert_eq!(host.identity_files, vec![PathBuf::from("/keys/default")]);
+    }
+
+    #[test]
+    fn test_load_follows_include() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let conf_d = tmp.path().join("conf.d");
+        fs::create_dir(&conf_d)?;
+        let mut included = fs::File::create(conf_d.join("bastion.conf"))?;
+        write!(included, "Host bastion\n  HostName 10.0.0.1\n  Port 2022\n")?;
+        let main = tmp.path().join("config");
+        fs::write(
+            &main,
+            format!("Include {}/*.conf\nHost *\n  User ops\n", conf_d.display()),
+        )?;
+
+        let host = SshConfig::load(&main)?.resolve("bastion");
+        assert_eq!(host.host_name.as_deref(), Some("10.0.0.1"));
+        assert_eq!(host.port, Some(2022));
+        assert_eq!(host.user.as_deref(), Some("ops"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_include_restores_enclosing_block() -> Result<()> {
+        let tmp = TempDir::new()?;
+        let included = tmp.path().join("other.conf");
+        fs::write(
+            &included,
+            "  Port 2022
+Host other
+  User bob
+",
+        )?;
+        let main = tmp.path().join("config");
+        fs::write(
+            &main,
+            format!(
+                "Host data
+  Include {}
+  User alice
+",
+                included.display()
+            ),
+        )?;
+
+        let config = SshConfig::load(&main)?;
+        let data = config.resolve("data");
+        assert_eq!(data.user.as_deref(), Some("alice"));
+        assert_eq!(data.port, Some(2022));
+        assert_eq!(config.resolve("other").user.as_deref(), Some("bob"));
+        Ok(())
+    }
+}
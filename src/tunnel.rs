@@ -0,0 +1,157 @@
+use std::{
+    io::{ErrorKind, Read, Write},
+    os::{fd::AsRawFd, unix::net::UnixStream},
+    thread,
+};
+
+use color_eyre::{Result, eyre::WrapErr};
+use ssh2::{BlockDirections, Channel, Session};
+
+/// Upper bound on one wait for either side, in milliseconds, in case libssh2 wants
+/// something the poll does not cover
+const MAX_IDLE_WAIT_MS: i32 = 1000;
+
+/// Open a `direct-tcpip` channel to `host:port` through an authenticated session and
+/// return a local socket carrying its traffic, ready to hand to `Session::set_tcp_stream`.
+///
+/// The session is moved onto a pump thread and switched to non-blocking mode, so it
+/// must not be used for anything else. The thread exits once either side closes.
+pub fn open(via: Session, host: &str, port: u16) -> Result<UnixStream> {
+    let channel = via
+        .channel_direct_tcpip(host, port, None)
+        .wrap_err_with(|| format!("Failed to open tunnel to {host}:{port}"))?;
+    let (ours, theirs) = UnixStream::pair()?;
+    ours.set_nonblocking(true)?;
+    via.set_blocking(false);
+    thread::spawn(move || pump(via, channel, ours));
+    Ok(theirs)
+}
+
+/// Shuttle bytes both ways until the socket or the channel closes
+fn pump(via: Session, mut channel: Channel, mut socket: UnixStream) {
+    let mut buf = vec![0; 32 * 1024];
+    // bytes read from one side that the other side has not accepted yet
+    let mut upstream: Vec<u8> = Vec::new();
+    let mut downstream: Vec<u8> = Vec::new();
+    loop {
+        let mut moved = false;
+
+        if upstream.is_empty() {
+            match socket.read(&mut buf) {
+                Ok(0) => break,
+                Ok(n) => {
+                    upstream.extend_from_slice(&buf[..n]);
+                    moved = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+        }
+        if !upstream.is_empty() {
+            match channel.write(&upstream) {
+                Ok(n) => {
+                    upstream.drain(..n);
+                    moved |= n > 0;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+        }
+
+        if downstream.is_empty() {
+            match channel.read(&mut buf) {
+                Ok(0) if channel.eof() => break,
+                Ok(n) => {
+                    downstream.extend_from_slice(&buf[..n]);
+                    moved |= n > 0;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+        }
+        if !downstream.is_empty() {
+            match socket.write(&downstream) {
+                Ok(n) => {
+                    downstream.drain(..n);
+                    moved |= n > 0;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(_) => break,
+            }
+        }
+
+        if !moved {
+            wait(&via, &socket, upstream.is_empty(), downstream.is_empty());
+        }
+    }
+    let _ = channel.close();
+}
+
+/// Sleep until one of the sides can make progress. The socket is watched for input
+/// only while there is room to take it, and for output while bytes wait for it; the
+/// session likewise, with libssh2 saying which way it is stuck.
+fn wait(via: &Session, socket: &UnixStream, read_socket: bool, read_channel: bool) {
+    let mut socket_events = 0;
+    if read_socket {
+        socket_events |= libc::POLLIN;
+    }
+    if !read_channel {
+        socket_events |= libc::POLLOUT;
+    }
+    let mut session_events = 0;
+    if read_channel {
+        session_events |= libc::POLLIN;
+    }
+    if matches!(
+        via.block_directions(),
+        BlockDirections::Outbound | BlockDirections::Both
+    ) {
+        session_events |= libc::POLLOUT;
+    }
+    let mut fds = [
+        libc::pollfd {
+            fd: socket.as_raw_fd(),
+            events: socket_events,
+            revents: 0,
+        },
+        libc::pollfd {
+            fd: via.as_raw_fd(),
+            events: session_events,
+            revents: 0,
+        },
+    ];
+    // SAFETY: `fds` is a live array of the length given. Errors such as EINTR just
+    // end the wait early.
+    unsafe {
+        libc::poll(
+            fds.as_mut_ptr(),
+            fds.len() as libc::nfds_t,
+            MAX_IDLE_WAIT_MS,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, Instant};
+
+    #[test]
+    fn test_wait_sleeps_until_input() -> Result<()> {
+        // the session only needs a descriptor to watch, it is never handshaken
+        let mut via = Session::new()?;
+        let (transport, _transport_peer) = UnixStream::pair()?;
+        via.set_tcp_stream(transport);
+        let (socket, mut peer) = UnixStream::pair()?;
+
+        let started = Instant::now();
+        wait(&via, &socket, true, true);
+        assert!(started.elapsed() >= Duration::from_millis(500));
+
+        peer.write_all(b"x")?;
+        let started = Instant::now();
+        wait(&via, &socket, true, true);
+        assert!(started.elapsed() < Duration::from_millis(500));
+        Ok(())
+    }
+}
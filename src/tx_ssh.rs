@@ -1,154 +1,1105 @@
+use crate::{
+    error::RemoteError,
+    find,
+    pool::{ConnectionPool, PooledSession},
+    probe::{self, Capabilities, StatFlavor},
+    shell::{self, RemoteCommand},
+    ssh::ConnectionProfile,
+    usage::{DirUsage, UsageBuilder},
+    walk::RemoteWalk,
+};
 use color_eyre::{
     Result,
-    eyre::{Context, ContextCompat, eyre},
+    eyre::{Context, eyre},
+};
+use ssh2::{Channel, FileStat, Session, Sftp};
+use std::{
+    borrow::Cow,
+    ffi::OsStr,
+    io::{self, ErrorKind, Read},
+    os::unix::ffi::OsStrExt,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, MutexGuard, OnceLock, TryLockError},
+    thread,
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
 };
-use ssh2::Session;
-use std::io::Read;
-use std::net::TcpStream;
-use std::time::Duration;
+use tokio::{sync::mpsc, task::JoinHandle};
+use tokio_util::sync::{CancellationToken, DropGuard};
 
-/// Execute a command on a remote host via SSH
-/// Returns (stdout, stderr, exit_code)
+/// Execute a command on a remote host via SSH.
+/// A non-zero exit is not an error here, see [`CommandOutput::check`].
+///
+/// The timeout covers only this command, the session's own timeout is left alone.
 pub fn execute_remote_command(
     session: &Session,
     command: &str,
     timeout_secs: Option<u32>,
-) -> Result<(String, String, i32)> {
-    // Create a new channel for this command
-    let mut channel = session
-        .channel_session()
-        .wrap_err("Failed to create SSH channel")?;
-
-    // Set timeout if specified
-    if let Some(timeout) = timeout_secs {
-        session.set_timeout(timeout * 1000); // ssh2 uses milliseconds
-    }
-
-    // Execute the command
-    channel
-        .exec(command)
-        .wrap_err_with(|| format!("Failed to execute command: {}", command))?;
-
-    // Read stdout
-    let mut stdout = String::new();
-    channel
-        .read_to_string(&mut stdout)
-        .wrap_err("Failed to read stdout")?;
-
-    // Read stderr
-    let mut stderr = String::new();
-    channel
-        .stderr()
-        .read_to_string(&mut stderr)
-        .wrap_err("Failed to read stderr")?;
-
-    // Wait for command to complete and get exit status
-    channel.wait_close().wrap_err("Failed to close channel")?;
-
-    let exit_code = channel
-        .exit_status()
-        .wrap_err("Failed to get exit status")?;
+) -> Result<CommandOutput, RemoteError> {
+    let deadline = timeout_secs.map(|secs| Instant::now() + Duration::from_secs(secs.into()));
+    collect_output(session, command, deadline, &CancellationToken::new())
+}
+
+/// How long the non-blocking read loop sleeps when neither stream had data
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Walking a large tree in one command can take a while
+const TREE_SCAN_TIMEOUT_SECS: u32 = 1800;
+
+/// How long an abandoned channel gets to close before it is dropped
+const CLOSE_GRACE: Duration = Duration::from_secs(1);
+
+/// Lines buffered between the reading thread and an async consumer
+const STREAM_BUFFER_LINES: usize = 1024;
+
+/// Everything a finished remote command produced
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandOutput {
+    /// Raw bytes, remote file names need not be UTF-8
+    pub stdout: Vec<u8>,
+    pub stderr: String,
+    pub exit_code: i32,
+    /// Name of the signal that killed the command, without the `SIG` prefix
+    pub signal: Option<String>,
+    pub duration: Duration,
+}
+
+impl CommandOutput {
+    pub fn success(&self) -> bool {
+        self.exit_code == 0 && self.signal.is_none()
+    }
+
+    pub fn stdout_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stdout)
+    }
+
+    /// Turn a failed command into [`RemoteError::NonZeroExit`]
+    pub fn check(self, command: &str) -> Result<Self, RemoteError> {
+        if self.success() {
+            return Ok(self);
+        }
+        Err(RemoteError::NonZeroExit {
+            command: command.to_string(),
+            exit_code: self.exit_code,
+            signal: self.signal,
+            stderr: self.stderr,
+        })
+    }
+}
+
+/// How a streamed command ended
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExitStatus {
+    pub code: i32,
+    pub signal: Option<String>,
+}
+
+/// A piece of command output, tagged with the stream it arrived on
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputChunk {
+    Stdout(Vec<u8>),
+    Stderr(Vec<u8>),
+}
 
-    Ok((stdout, stderr, exit_code))
+/// One complete line of command output, without its line ending
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum OutputLine {
+    Stdout(String),
+    Stderr(String),
 }
 
-/// Async version using tokio (more suitable for your TUI)
+/// Async version using tokio (more suitable for your TUI).
+///
+/// The command runs on a session checked out of the pool on a blocking thread, so
+/// the runtime is never stalled. It stops early, closing its channel, when `cancel`
+/// fires or `timeout_secs` elapses.
 pub async fn execute_remote_command_async(
-    session: &Session,
+    pool: &ConnectionPool,
+    profile: &ConnectionProfile,
     command: &str,
     timeout_secs: Option<u64>,
-) -> Result<(String, String, i32)> {
-    // Clone session for async operation
+    cancel: CancellationToken,
+) -> Result<CommandOutput, RemoteError> {
+    let pool = pool.clone();
+    let profile = profile.clone();
     let command = command.to_string();
-    let timeout = timeout_secs;
+    let deadline = timeout_secs.map(|secs| Instant::now() + Duration::from_secs(secs));
+
+    let task = tokio::task::spawn_blocking({
+        let cancel = cancel.clone();
+        let command = command.clone();
+        move || {
+            let session = checkout(&pool, &profile, &command, deadline, &cancel)?;
+            collect_output(&session, &command, deadline, &cancel)
+        }
+    });
+    // the blocking side may be stuck connecting, so stop waiting for it here; it sees
+    // the token before running anything
+    let started = Instant::now();
+    let expired = async {
+        match deadline {
+            Some(deadline) => tokio::time::sleep_until(deadline.into()).await,
+            None => std::future::pending().await,
+        }
+    };
+    tokio::select! {
+        joined = task => {
+            joined.map_err(|e| RemoteError::channel("Async command execution failed", e))?
+        }
+        _ = cancel.cancelled() => Err(RemoteError::Cancelled { command }),
+        _ = expired => {
+            cancel.cancel();
+            Err(RemoteError::Timeout { command, after: started.elapsed() })
+        }
+    }
+}
+
+/// Check out a session for `command`, honouring its deadline and cancellation
+fn checkout(
+    pool: &ConnectionPool,
+    profile: &ConnectionProfile,
+    command: &str,
+    deadline: Option<Instant>,
+    cancel: &CancellationToken,
+) -> Result<PooledSession, RemoteError> {
+    let started = Instant::now();
+    pool.get_within(profile, deadline, Some(cancel))
+        .map_err(|e| match RemoteError::from(e) {
+            RemoteError::Cancelled { .. } => RemoteError::Cancelled {
+                command: command.to_string(),
+            },
+            RemoteError::Timeout { .. } => RemoteError::Timeout {
+                command: command.to_string(),
+                after: started.elapsed(),
+            },
+            other => other,
+        })
+}
+
+/// Run a command and buffer all of its output
+fn collect_output(
+    session: &Session,
+    command: &str,
+    deadline: Option<Instant>,
+    cancel: &CancellationToken,
+) -> Result<CommandOutput, RemoteError> {
+    let started = Instant::now();
+    let mut stdout = Vec::new();
+    let mut stderr = Vec::new();
+    let status = stream_remote_command(session, command, deadline, cancel, |chunk| match chunk {
+        OutputChunk::Stdout(bytes) => stdout.extend_from_slice(&bytes),
+        OutputChunk::Stderr(bytes) => stderr.extend_from_slice(&bytes),
+    })?;
+    Ok(CommandOutput {
+        stdout,
+        stderr: String::from_utf8_lossy(&stderr).into_owned(),
+        exit_code: status.code,
+        signal: status.signal,
+        duration: started.elapsed(),
+    })
+}
+
+/// Run a command, handing each chunk of stdout and stderr to `on_chunk` in the order
+/// it arrives, and return how it exited.
+///
+/// The session is switched to non-blocking mode for the whole command, so neither
+/// stream can fill its window and stall the other, and `cancel` and the deadline are
+/// honoured even while the channel is being opened or closed. The session must not be
+/// used by anyone else meanwhile, not even an open SFTP file; a pooled checkout
+/// guarantees that. Its previous blocking mode is restored afterwards.
+pub fn stream_remote_command(
+    session: &Session,
+    command: &str,
+    deadline: Option<Instant>,
+    cancel: &CancellationToken,
+    on_chunk: impl FnMut(OutputChunk),
+) -> Result<ExitStatus, RemoteError> {
+    let started = Instant::now();
+    let was_blocking = session.is_blocking();
+    session.set_blocking(false);
+    let result = run_channel(session, command, deadline, cancel, on_chunk);
+    session.set_blocking(was_blocking);
+
+    result.map_err(|stop| match stop {
+        Stop::Cancelled => RemoteError::Cancelled {
+            command: command.to_string(),
+        },
+        Stop::TimedOut => RemoteError::Timeout {
+            command: command.to_string(),
+            after: started.elapsed(),
+        },
+        Stop::Failed(context, e) => RemoteError::channel(context, e),
+    })
+}
 
-    // Run the blocking operation in a separate thread
-    tokio::task::spawn_blocking(move || {
-        // Note: In real implementation, you'd need to pass the session properly
-        // This is simplified for the prototype
+/// Open a channel, run `command` on it and read it to the end, on a non-blocking session
+fn run_channel(
+    session: &Session,
+    command: &str,
+    deadline: Option<Instant>,
+    cancel: &CancellationToken,
+    on_chunk: impl FnMut(OutputChunk),
+) -> Result<ExitStatus, Stop> {
+    let mut channel = retry(deadline, cancel, "Failed to create SSH channel", || {
+        session.channel_session()
+    })?;
+    let result = retry(deadline, cancel, "Failed to execute command", || {
+        channel.exec(command)
+    })
+    .and_then(|()| drain_channel(&mut channel, deadline, cancel, on_chunk))
+    .and_then(|()| {
+        retry(deadline, cancel, "Failed to close channel", || {
+            channel.wait_close()
+        })
+    });
+    if let Err(stop) = result {
+        // closing the channel hangs up on the remote process
+        let grace = Some(Instant::now() + CLOSE_GRACE);
+        let _ = retry(grace, &CancellationToken::new(), "", || channel.close());
+        return Err(stop);
+    }
+
+    let code = channel
+        .exit_status()
+        .map_err(|e| Stop::Failed("Failed to get exit status", e.into()))?;
+    let signal = channel
+        .exit_signal()
+        .ok()
+        .and_then(|signal| signal.exit_signal);
+    Ok(ExitStatus { code, signal })
+}
+
+/// Like [`stream_remote_command`], but split into lines. A final line without a
+/// trailing newline is still delivered.
+pub fn stream_remote_lines(
+    session: &Session,
+    command: &str,
+    deadline: Option<Instant>,
+    cancel: &CancellationToken,
+    mut on_line: impl FnMut(OutputLine),
+) -> Result<ExitStatus, RemoteError> {
+    let mut stdout = LineBuffer::default();
+    let mut stderr = LineBuffer::default();
+    let status = stream_remote_command(session, command, deadline, cancel, |chunk| match chunk {
+        OutputChunk::Stdout(bytes) => stdout.push(&bytes, |l| on_line(OutputLine::Stdout(l))),
+        OutputChunk::Stderr(bytes) => stderr.push(&bytes, |l| on_line(OutputLine::Stderr(l))),
+    });
+    stdout.finish(|l| on_line(OutputLine::Stdout(l)));
+    stderr.finish(|l| on_line(OutputLine::Stderr(l)));
+    status
+}
 
-        // For now, this is a placeholder showing the structure
-        // You'd need to restructure to use async SSH libraries like russh
-        Ok(("stdout".to_string(), "stderr".to_string(), 0))
+/// Output lines of a command running on a pooled session, see [`stream_remote_command_async`]
+pub struct RemoteStream {
+    lines: mpsc::Receiver<OutputLine>,
+    task: JoinHandle<Result<ExitStatus, RemoteError>>,
+    /// Cancels the command when the stream is dropped
+    _cancel_on_drop: DropGuard,
+}
+
+impl RemoteStream {
+    /// The next line from either stream, `None` once the command has finished
+    pub async fn next_line(&mut self) -> Option<OutputLine> {
+        self.lines.recv().await
+    }
+
+    /// Wait for the command to exit, discarding any lines not read yet
+    pub async fn finish(mut self) -> Result<ExitStatus, RemoteError> {
+        // keep reading, or the command would be cancelled for lack of a listener
+        while self.lines.recv().await.is_some() {}
+        (&mut self.task)
+            .await
+            .map_err(|e| RemoteError::channel("Async command execution failed", e))?
+    }
+}
+
+/// Start a command on a pooled session and stream its output lines as they arrive.
+/// The reader is throttled when lines are not consumed, so huge outputs are never
+/// held in memory, and dropping the stream cancels the command.
+pub fn stream_remote_command_async(
+    pool: &ConnectionPool,
+    profile: &ConnectionProfile,
+    command: &str,
+    timeout_secs: Option<u64>,
+    cancel: CancellationToken,
+) -> RemoteStream {
+    let pool = pool.clone();
+    let profile = profile.clone();
+    let command = command.to_string();
+    let deadline = timeout_secs.map(|secs| Instant::now() + Duration::from_secs(secs));
+    spawn_stream(cancel, move |cancel, on_line| {
+        let session = checkout(&pool, &profile, &command, deadline, cancel)?;
+        stream_remote_lines(&session, &command, deadline, cancel, on_line)
     })
-    .await
-    .wrap_err("Async command execution failed")?
+}
+
+/// Run `run` on a blocking thread, feeding the lines it produces to a [`RemoteStream`]
+fn spawn_stream<F>(cancel: CancellationToken, run: F) -> RemoteStream
+where
+    F: FnOnce(&CancellationToken, &mut dyn FnMut(OutputLine)) -> Result<ExitStatus, RemoteError>
+        + Send
+        + 'static,
+{
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER_LINES);
+    let cancel = cancel.child_token();
+    let task = tokio::task::spawn_blocking({
+        let cancel = cancel.clone();
+        move || {
+            run(&cancel, &mut |line| {
+                // stop the command once nobody is listening any more
+                if tx.blocking_send(line).is_err() {
+                    cancel.cancel();
+                }
+            })
+        }
+    });
+    RemoteStream {
+        lines: rx,
+        task,
+        _cancel_on_drop: cancel.drop_guard(),
+    }
+}
+
+/// Why a command was abandoned before it finished
+enum Stop {
+    Cancelled,
+    TimedOut,
+    Failed(&'static str, io::Error),
+}
+
+/// Fail with [`Stop`] once the command is cancelled or past its deadline
+fn check_stop(deadline: Option<Instant>, cancel: &CancellationToken) -> Result<(), Stop> {
+    if cancel.is_cancelled() {
+        return Err(Stop::Cancelled);
+    }
+    if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+        return Err(Stop::TimedOut);
+    }
+    Ok(())
+}
+
+/// Repeat a non-blocking libssh2 call until it stops asking to be retried
+fn retry<T>(
+    deadline: Option<Instant>,
+    cancel: &CancellationToken,
+    context: &'static str,
+    mut op: impl FnMut() -> Result<T, ssh2::Error>,
+) -> Result<T, Stop> {
+    loop {
+        check_stop(deadline, cancel)?;
+        match op().map_err(io::Error::from) {
+            Ok(value) => return Ok(value),
+            Err(e) if e.kind() == ErrorKind::WouldBlock => thread::sleep(POLL_INTERVAL),
+            Err(e) => return Err(Stop::Failed(context, e)),
+        }
+    }
+}
+
+/// Read stdout and stderr of a non-blocking channel until EOF
+fn drain_channel(
+    channel: &mut Channel,
+    deadline: Option<Instant>,
+    cancel: &CancellationToken,
+    mut on_chunk: impl FnMut(OutputChunk),
+) -> Result<(), Stop> {
+    let mut buf = vec![0; 32 * 1024];
+    loop {
+        check_stop(deadline, cancel)?;
+
+        let mut progressed = false;
+        for stream in [0, ssh2::EXTENDED_DATA_STDERR] {
+            match channel.stream(stream).read(&mut buf) {
+                Ok(0) => {}
+                Ok(n) => {
+                    let bytes = buf[..n].to_vec();
+                    on_chunk(if stream == 0 {
+                        OutputChunk::Stdout(bytes)
+                    } else {
+                        OutputChunk::Stderr(bytes)
+                    });
+                    progressed = true;
+                }
+                Err(e) if e.kind() == ErrorKind::WouldBlock => {}
+                Err(e) => return Err(Stop::Failed("Failed to read command output", e)),
+            }
+        }
+
+        if !progressed {
+            if channel.eof() {
+                return Ok(());
+            }
+            thread::sleep(POLL_INTERVAL);
+        }
+    }
+}
+
+/// Splits a byte stream into lines across chunk boundaries
+#[derive(Default)]
+struct LineBuffer {
+    partial: Vec<u8>,
+}
+
+impl LineBuffer {
+    fn push(&mut self, bytes: &[u8], mut on_line: impl FnMut(String)) {
+        self.partial.extend_from_slice(bytes);
+        let mut start = 0;
+        while let Some(end) = self.partial[start..].iter().position(|b| *b == b'\n') {
+            on_line(line_from(&self.partial[start..start + end]));
+            start += end + 1;
+        }
+        self.partial.drain(..start);
+    }
+
+    fn finish(&mut self, mut on_line: impl FnMut(String)) {
+        if !self.partial.is_empty() {
+            on_line(line_from(&self.partial));
+            self.partial.clear();
+        }
+    }
+}
+
+fn line_from(bytes: &[u8]) -> String {
+    let bytes = bytes.strip_suffix(b"\r").unwrap_or(bytes);
+    String::from_utf8_lossy(bytes).into_owned()
 }
 
 /// Higher-level wrapper for common file operations
 pub struct RemoteFileOperations<'a> {
-    session: &'a Session,
+    target: Target<'a>,
     default_timeout: u32,
+    /// Only used for a fixed session, the pool keeps its own cache
+    capabilities: OnceLock<Arc<Capabilities>>,
+    /// Held while a fixed session is in use, see [`RemoteFileOperations::claim`]
+    busy: Mutex<()>,
+}
+
+/// Keeps the session behind an open remote file to the file alone
+pub(crate) struct SessionHold<'a> {
+    _checkout: Option<PooledSession>,
+    _claim: Option<MutexGuard<'a, ()>>,
+}
+
+/// Where commands run: a fixed session, or the pool which can reconnect
+enum Target<'a> {
+    Session(&'a Session),
+    Pool {
+        pool: &'a ConnectionPool,
+        profile: &'a ConnectionProfile,
+    },
 }
 
 impl<'a> RemoteFileOperations<'a> {
+    /// Work on a fixed session, which nothing else may use while this is alive
     pub fn new(session: &'a Session) -> Self {
         Self {
-            session,
+            target: Target::Session(session),
             default_timeout: 30, // 30 seconds default
+            capabilities: OnceLock::new(),
+            busy: Mutex::new(()),
+        }
+    }
+
+    /// Run through the pool, so commands survive a dropped session
+    pub fn pooled(pool: &'a ConnectionPool, profile: &'a ConnectionProfile) -> Self {
+        Self {
+            target: Target::Pool { pool, profile },
+            default_timeout: 30,
+            capabilities: OnceLock::new(),
+            busy: Mutex::new(()),
+        }
+    }
+
+    /// Claim the fixed session for one operation. An open remote file keeps its claim
+    /// until dropped, so commands fail straight away instead of tripping over it.
+    fn claim(&self) -> Result<MutexGuard<'_, ()>> {
+        match self.busy.try_lock() {
+            Ok(guard) => Ok(guard),
+            Err(TryLockError::Poisoned(e)) => Ok(e.into_inner()),
+            Err(TryLockError::WouldBlock) => Err(eyre!(
+                "The session is in use by an open remote file, drop it first"
+            )),
+        }
+    }
+
+    /// Run an operation that is safe to repeat, retrying on a fresh session if the
+    /// pool's reconnect policy allows it
+    fn with_session<T>(&self, mut op: impl FnMut(&Session) -> Result<T>) -> Result<T> {
+        match self.target {
+            Target::Session(session) => {
+                let _claim = self.claim()?;
+                op(session)
+            }
+            Target::Pool { pool, profile } => pool.with_reconnect(profile, op),
+        }
+    }
+
+    /// An SFTP channel to hold on to, with what keeps its session to itself
+    pub(crate) fn sftp_checkout(&self) -> Result<(Sftp, SessionHold<'_>)> {
+        let start = |session: &Session| {
+            session
+                .sftp()
+                .map_err(|e| RemoteError::channel("Failed to start SFTP", e))
+        };
+        match self.target {
+            Target::Session(session) => {
+                let claim = self.claim()?;
+                let hold = SessionHold {
+                    _checkout: None,
+                    _claim: Some(claim),
+                };
+                Ok((start(session)?, hold))
+            }
+            Target::Pool { pool, profile } => {
+                let session = pool.get(profile)?;
+                let sftp = start(&session)?;
+                let hold = SessionHold {
+                    _checkout: Some(session),
+                    _claim: None,
+                };
+                Ok((sftp, hold))
+            }
+        }
+    }
+
+    /// Run a command exactly once, for changes that must not be repeated blindly if
+    /// the session drops halfway
+    fn run_once(&self, command: &str, timeout_secs: u32) -> Result<CommandOutput> {
+        let output = match self.target {
+            Target::Session(session) => {
+                let _claim = self.claim()?;
+                execute_remote_command(session, command, Some(timeout_secs))?
+            }
+            Target::Pool { pool, profile } => {
+                let session = pool.get(profile)?;
+                execute_remote_command(&session, command, Some(timeout_secs))?
+            }
+        };
+        Ok(output.check(command)?)
+    }
+
+    /// Run a command that is safe to repeat. Failures are [`RemoteError`]s inside the report.
+    fn run_idempotent(&self, command: &str, timeout_secs: u32) -> Result<CommandOutput> {
+        self.with_session(|session| {
+            Ok(execute_remote_command(
+                session,
+                command,
+                Some(timeout_secs),
+            )?)
+        })
+    }
+
+    /// List directory contents with detailed information, over SFTP where the host
+    /// offers it, then from `find`, and only as a last resort from `ls`
+    pub fn list_directory(&self, path: impl AsRef<Path>) -> Result<Vec<FileInfo>> {
+        let capabilities = self.capabilities()?;
+        if capabilities.sftp {
+            self.list_directory_sftp(path.as_ref())
+        } else if capabilities.find_printf {
+            self.list_find(path.as_ref(), false)
+        } else {
+            self.list_directory_ls(path.as_ref())
+        }
+    }
+
+    /// Everything below `path` in a single `find` run. Needs GNU find on the host.
+    pub fn list_recursive(&self, path: impl AsRef<Path>) -> Result<Vec<FileInfo>> {
+        if !self.capabilities()?.find_printf {
+            return Err(eyre!("Recursive listing needs GNU find on the remote host"));
+        }
+        self.list_find(path.as_ref(), true)
+    }
+
+    fn list_find(&self, path: &Path, recursive: bool) -> Result<Vec<FileInfo>> {
+        let command: String = find::command(path, recursive).into();
+        let timeout = if recursive {
+            TREE_SCAN_TIMEOUT_SECS
+        } else {
+            self.default_timeout
+        };
+        let output = self.run_idempotent(&command, timeout)?.check(&command)?;
+        find::parse(&output.stdout)
+    }
+
+    /// List a directory with SFTP `readdir`, exact names and all attributes included
+    pub fn list_directory_sftp(&self, path: &Path) -> Result<Vec<FileInfo>> {
+        self.with_sftp(|sftp| {
+            let entries = sftp.readdir(path).map_err(|e| RemoteError::sftp(path, e))?;
+            Ok(entries
+                .into_iter()
+                .map(|(path, stat)| {
+                    let mut info = FileInfo::from_sftp(&path, &stat);
+                    if info.is_symlink {
+                        // readdir leaves link targets out, left unset if the link vanished
+                        info.symlink_target = sftp.readlink(&path).ok();
+                    }
+                    info
+                })
+                .collect())
+        })
+    }
+
+    /// Run SFTP requests that are safe to repeat, each limited to the default timeout
+    fn with_sftp<T>(&self, mut op: impl FnMut(&Sftp) -> Result<T>) -> Result<T> {
+        let timeout_ms = self.default_timeout.saturating_mul(1000);
+        self.with_session(|session| {
+            // SFTP calls block, so the session timeout is what bounds them
+            let previous = session.timeout();
+            session.set_timeout(timeout_ms);
+            let result = session
+                .sftp()
+                .map_err(|e| RemoteError::channel("Failed to start SFTP", e).into())
+                .and_then(|sftp| op(&sftp));
+            session.set_timeout(previous);
+            result
+        })
+    }
+
+    fn list_directory_ls(&self, path: &Path) -> Result<Vec<FileInfo>> {
+        let command: String = ls_command(&*self.capabilities()?, path).into();
+        let output = self
+            .run_idempotent(&command, self.default_timeout)?
+            .check(&command)?;
+
+        let mut files = parse_ls_output(&output.stdout_lossy());
+        // ls prints bare names, the other backends full paths
+        for file in &mut files {
+            file.raw_path = path.join(&file.raw_path);
+            file.path = file.raw_path.to_string_lossy().into_owned();
         }
+        Ok(files)
     }
 
-    /// List directory contents with detailed information
-    pub fn list_directory(&self, path: &str) -> Result<Vec<FileInfo>> {
-        let command = format!("ls -lA '{}'", path.replace("'", "'\"'\"'"));
-        let (stdout, stderr, exit_code) =
-            execute_remote_command(self.session, &command, Some(self.default_timeout))?;
+    /// Size and file count of every directory below `path`, with the `largest` biggest
+    /// files at each level. Directories that cannot be read are skipped and reported.
+    pub fn disk_usage(&self, path: impl AsRef<Path>, largest: usize) -> Result<DiskUsage> {
+        // the paths find prints start with the root exactly as it was passed in
+        let path = find::search_root(&self.expand_tilde(path)?);
+        let mut errors = Vec::new();
+        let root = if self.capabilities()?.find_printf {
+            let command: String = find::command(&path, true).into();
+            self.with_session(|session| {
+                errors.clear();
+                stream_usage(session, &command, &path, largest, &mut errors)
+            })?
+        } else {
+            let mut builder = UsageBuilder::new(&path, largest);
+            for item in RemoteWalk::new(self, &path) {
+                match item {
+                    Ok(entry) => builder.add(entry.info),
+                    Err(error) => errors.push(error.to_string()),
+                }
+            }
+            builder.finish()
+        };
+
+        Ok(DiskUsage { root, errors })
+    }
+
+    /// Expand a leading `~` or `~user` the way the remote shell would
+    pub fn expand_tilde(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = path.as_ref();
+        if !path.as_os_str().as_bytes().starts_with(b"~") {
+            return Ok(path.to_path_buf());
+        }
+        let command: String = shell::expand_tilde(path).into();
+        let output = self
+            .run_idempotent(&command, self.default_timeout)?
+            .check(&command)?;
+        nul_separated(&output.stdout)
+            .next()
+            .ok_or_else(|| eyre!("No expansion for {}", path.display()))
+    }
+
+    /// The canonical absolute form of `path`, symlinks resolved, after tilde expansion
+    pub fn realpath(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = self.expand_tilde(path)?;
+        if self.capabilities()?.sftp {
+            return self.with_sftp(|sftp| {
+                Ok(sftp
+                    .realpath(&path)
+                    .map_err(|e| RemoteError::sftp(&path, e))?)
+            });
+        }
+        let command: String = RemoteCommand::new("realpath").path(&path).into();
+        let output = self
+            .run_idempotent(&command, self.default_timeout)?
+            .check(&command)?;
+        let resolved = output.stdout.strip_suffix(b"\n").unwrap_or(&output.stdout);
+        Ok(PathBuf::from(OsStr::from_bytes(resolved)))
+    }
 
-        if exit_code != 0 {
-            return Err(eyre!("ls command failed: {}", stderr));
+    /// Target of the symlink at `path`, as stored in the link
+    pub fn read_link(&self, path: impl AsRef<Path>) -> Result<PathBuf> {
+        let path = path.as_ref();
+        if self.capabilities()?.sftp {
+            return self.with_sftp(|sftp| {
+                Ok(sftp
+                    .readlink(path)
+                    .map_err(|e| RemoteError::sftp(path, e))?)
+            });
         }
+        let command: String = RemoteCommand::new("readlink").path(path).into();
+        let output = self
+            .run_idempotent(&command, self.default_timeout)?
+            .check(&command)?;
+        let target = output.stdout.strip_suffix(b"\n").unwrap_or(&output.stdout);
+        Ok(PathBuf::from(OsStr::from_bytes(target)))
+    }
+
+    /// Paths matching a glob pattern such as `~/data/run_*/`, sorted. A pattern
+    /// without wildcards yields itself if it exists.
+    pub fn glob(&self, pattern: impl AsRef<Path>) -> Result<Vec<PathBuf>> {
+        let command: String = shell::glob(pattern.as_ref()).into();
+        let output = self
+            .run_idempotent(&command, self.default_timeout)?
+            .check(&command)?;
+        let mut matches: Vec<PathBuf> = nul_separated(&output.stdout).collect();
+        matches.sort();
+        Ok(matches)
+    }
 
-        Ok(parse_ls_output(&stdout))
+    /// Whether `path` is a directory, following symlinks
+    pub fn is_directory(&self, path: impl AsRef<Path>) -> Result<bool> {
+        // test takes no `--`, but with two arguments the operand is never an option
+        let command: String = RemoteCommand::new("test")
+            .arg("-d")
+            .arg(path.as_ref())
+            .into();
+        let output = self.run_idempotent(&command, self.default_timeout)?;
+        if output.exit_code == 1 && output.signal.is_none() {
+            return Ok(false);
+        }
+        output.check(&command)?;
+        Ok(true)
     }
 
     /// Get file/directory information
-    pub fn stat_file(&self, path: &str) -> Result<FileInfo> {
-        let command = format!("stat -c '%F|%s|%Y|%n' '{}'", path.replace("'", "'\"'\"'"));
-        let (stdout, stderr, exit_code) =
-            execute_remote_command(self.session, &command, Some(self.default_timeout))?;
+    pub fn stat_file(&self, path: impl AsRef<Path>) -> Result<FileInfo> {
+        let path = path.as_ref();
+        let (flag, format) = stat_format(self.capabilities()?.stat);
+        let command: String = RemoteCommand::new("stat")
+            .args([flag, format])
+            .path(path)
+            .into();
+        let output = self
+            .run_idempotent(&command, self.default_timeout)?
+            .check(&command)?;
 
-        if exit_code != 0 {
-            return Err(eyre!("stat command failed: {}", stderr));
+        let mut file_info = parse_stat_output(output.stdout_lossy().trim_end_matches('\n'))?;
+        file_info.raw_path = path.to_path_buf();
+        if file_info.is_symlink {
+            file_info.symlink_target = Some(self.read_link(path)?);
         }
+        Ok(file_info)
+    }
 
-        parse_stat_output(&stdout.trim())
+    /// What the remote system supports, probed on first use
+    pub fn capabilities(&self) -> Result<Arc<Capabilities>> {
+        match self.target {
+            Target::Session(session) => {
+                if let Some(capabilities) = self.capabilities.get() {
+                    return Ok(capabilities.clone());
+                }
+                let claim = self.claim()?;
+                let capabilities = Arc::new(probe::probe(session)?);
+                drop(claim);
+                Ok(self.capabilities.get_or_init(|| capabilities).clone())
+            }
+            Target::Pool { pool, profile } => pool.capabilities(profile),
+        }
     }
 
     /// Check if rsync is available on the remote system
     pub fn check_rsync_available(&self) -> Result<bool> {
-        let (_, _, exit_code) = execute_remote_command(
-            self.session,
-            "which rsync",
-            Some(5), // Short timeout for availability check
-        )?;
-
-        Ok(exit_code == 0)
+        Ok(self.capabilities()?.rsync.is_some())
     }
 
     /// Execute rsync dry-run to get transfer information
-    pub fn rsync_dry_run(&self, source: &str, dest: &str) -> Result<String> {
-        let command = format!(
-            "rsync -avun --itemize-changes '{}' '{}'",
-            source.replace("'", "'\"'\"'"),
-            dest.replace("'", "'\"'\"'")
-        );
+    pub fn rsync_dry_run(
+        &self,
+        source: impl AsRef<Path>,
+        dest: impl AsRef<Path>,
+    ) -> Result<String> {
+        let command: String = RemoteCommand::new("rsync")
+            .args(["-avun", "--itemize-changes"])
+            .path(source.as_ref())
+            .path(dest.as_ref())
+            .into();
 
-        let (stdout, stderr, exit_code) = execute_remote_command(
-            self.session,
-            &command,
-            Some(300), // 5 minutes for large directory scans
-        )?;
+        let output = self
+            .run_idempotent(
+                &command, 300, // 5 minutes for large directory scans
+            )?
+            .check(&command)?;
 
-        if exit_code != 0 {
-            return Err(eyre!("rsync dry-run failed: {}", stderr));
-        }
+        Ok(output.stdout_lossy().into_owned())
+    }
+
+    /// Create a directory and any missing parents, like `mkdir -p`
+    pub fn create_dir_all(&self, path: impl AsRef<Path>) -> Result<()> {
+        let command: String = RemoteCommand::new("mkdir")
+            .arg("-p")
+            .path(path.as_ref())
+            .into();
+        self.run_idempotent(&command, self.default_timeout)?
+            .check(&command)?;
+        Ok(())
+    }
+
+    /// Rename or move `from` to `to`, with `mv` semantics: an existing directory
+    /// `to` receives `from` inside it
+    pub fn rename(&self, from: impl AsRef<Path>, to: impl AsRef<Path>) -> Result<()> {
+        let command: String = RemoteCommand::new("mv")
+            .path(from.as_ref())
+            .path(to.as_ref())
+            .into();
+        self.run_once(&command, self.default_timeout)?;
+        Ok(())
+    }
+
+    /// Remove a file or symlink, or an empty directory
+    pub fn remove(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let program = if self.stat_file(path)?.is_directory {
+            "rmdir"
+        } else {
+            "rm"
+        };
+        let command: String = RemoteCommand::new(program).path(path).into();
+        self.run_once(&command, self.default_timeout)?;
+        Ok(())
+    }
+
+    /// Size up a tree for [`RemoteFileOperations::remove_tree`], which only accepts
+    /// the returned token, so nothing is deleted recursively without first showing
+    /// what it holds
+    pub fn confirm_remove_tree(&self, path: impl AsRef<Path>) -> Result<DeleteToken> {
+        let path = self.expand_tilde(path)?;
+        // only the parent is resolved, so a symlink given here is never followed
+        let (parent, name) = split_tree_root(&path)?;
+        let path = self.realpath(parent)?.join(name);
+        check_deletable(&path, &self.realpath("~")?)?;
+        check_not_symlink(&self.stat_file(&path)?)?;
+        let usage = self.disk_usage(&path, 0)?;
+        Ok(DeleteToken {
+            path,
+            files: usage.root.files,
+            bytes: usage.root.bytes,
+        })
+    }
+
+    /// Delete a whole tree, like `rm -rf`
+    pub fn remove_tree(&self, token: DeleteToken) -> Result<()> {
+        let command: String = RemoteCommand::new("rm").arg("-rf").path(&token.path).into();
+        self.run_once(&command, TREE_SCAN_TIMEOUT_SECS)?;
+        Ok(())
+    }
+
+    /// Set the permission bits, e.g. `0o750`
+    pub fn set_mode(&self, path: impl AsRef<Path>, mode: u32) -> Result<()> {
+        let command: String = chmod_command(path.as_ref(), mode).into();
+        self.run_idempotent(&command, self.default_timeout)?
+            .check(&command)?;
+        Ok(())
+    }
+
+    /// Change owner, group or both, by name or numeric id
+    pub fn set_owner(
+        &self,
+        path: impl AsRef<Path>,
+        owner: Option<&str>,
+        group: Option<&str>,
+    ) -> Result<()> {
+        let Some(command) = chown_command(path.as_ref(), owner, group) else {
+            return Ok(());
+        };
+        let command: String = command.into();
+        self.run_idempotent(&command, self.default_timeout)?
+            .check(&command)?;
+        Ok(())
+    }
+
+    /// Create a symlink at `link` pointing to `target`, which is stored as given
+    pub fn symlink(&self, target: impl AsRef<Path>, link: impl AsRef<Path>) -> Result<()> {
+        let command: String = RemoteCommand::new("ln")
+            .arg("-s")
+            .path(target.as_ref())
+            .path(link.as_ref())
+            .into();
+        self.run_once(&command, self.default_timeout)?;
+        Ok(())
+    }
+}
+
+/// Proof that a tree was sized up before being deleted, see
+/// [`RemoteFileOperations::confirm_remove_tree`]
+#[derive(Debug)]
+pub struct DeleteToken {
+    path: PathBuf,
+    files: u64,
+    bytes: u64,
+}
+
+impl DeleteToken {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn files(&self) -> u64 {
+        self.files
+    }
+
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+}
+
+/// `chmod` with options ended before the mode, not just before the path
+fn chmod_command(path: &Path, mode: u32) -> RemoteCommand {
+    RemoteCommand::new("chmod")
+        .end_options()
+        .arg(format!("{:o}", mode & 0o7777))
+        .path(path)
+}
+
+/// `chown` with options ended before the owner spec, `None` when there is nothing
+/// to change
+fn chown_command(path: &Path, owner: Option<&str>, group: Option<&str>) -> Option<RemoteCommand> {
+    let spec = match (owner, group) {
+        (Some(owner), Some(group)) => format!("{owner}:{group}"),
+        (Some(owner), None) => owner.to_string(),
+        (None, Some(group)) => format!(":{group}"),
+        (None, None) => return None,
+    };
+    Some(
+        RemoteCommand::new("chown")
+            .end_options()
+            .arg(spec)
+            .path(path),
+    )
+}
+
+/// Trees holding the system itself, nothing in them is deleted recursively
+const SYSTEM_TREES: &[&str] = &[
+    "/bin", "/boot", "/dev", "/etc", "/lib", "/lib64", "/proc", "/sbin", "/sys", "/usr",
+    "/Library", "/System",
+];
+
+/// Directories whose entries are system state, such as `/var/lib`
+const SYSTEM_PARENTS: &[&str] = &["/var"];
+
+/// Refuse recursive deletes of paths no transfer tool should ever remove: anything
+/// near the root, system trees, and the login user's home or a directory above it
+fn check_deletable(path: &Path, home: &Path) -> Result<(), RemoteError> {
+    let depth = path
+        .components()
+        .filter(|component| matches!(component, Component::Normal(_)))
+        .count();
+    let system = SYSTEM_TREES.iter().any(|tree| path.starts_with(tree))
+        || path
+            .parent()
+            .is_some_and(|parent| SYSTEM_PARENTS.iter().any(|dir| parent == Path::new(dir)));
+    if !path.is_absolute() || depth < 2 || system || home.starts_with(path) {
+        return Err(RemoteError::Refused(format!(
+            "Not deleting {} recursively",
+            path.display()
+        )));
+    }
+    Ok(())
+}
 
-        Ok(stdout)
+/// Parent and last component of a tree to delete, which must name an entry rather
+/// than `.` or `..`
+fn split_tree_root(path: &Path) -> Result<(&Path, &OsStr), RemoteError> {
+    let Some(name) = path.file_name() else {
+        return Err(RemoteError::Refused(format!(
+            "Not deleting {} recursively",
+            path.display()
+        )));
+    };
+    let parent = match path.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => parent,
+        _ => Path::new("."),
+    };
+    Ok((parent, name))
+}
+
+/// Refuse a symlink as the root of a tree to delete, it is removed on its own
+fn check_not_symlink(root: &FileInfo) -> Result<(), RemoteError> {
+    if root.is_symlink {
+        return Err(RemoteError::Refused(format!(
+            "Not deleting {} recursively, it is a symlink",
+            root.raw_path.display()
+        )));
+    }
+    Ok(())
+}
+
+/// Sum up `find` output as it arrives
+fn stream_usage(
+    session: &Session,
+    command: &str,
+    path: &Path,
+    largest: usize,
+    errors: &mut Vec<String>,
+) -> Result<DirUsage> {
+    let deadline = Instant::now() + Duration::from_secs(TREE_SCAN_TIMEOUT_SECS.into());
+    let cancel = CancellationToken::new();
+    let mut builder = UsageBuilder::new(path, largest);
+    let mut parser = find::RecordParser::default();
+    let mut parse_error = None;
+    let mut stderr = Vec::new();
+    let status =
+        stream_remote_command(
+            session,
+            command,
+            Some(deadline),
+            &cancel,
+            |chunk| match chunk {
+                OutputChunk::Stdout(bytes) if parse_error.is_none() => {
+                    if let Err(e) = parser.push(&bytes, |info| builder.add(info)) {
+                        parse_error = Some(e);
+                        cancel.cancel();
+                    }
+                }
+                OutputChunk::Stdout(_) => {}
+                OutputChunk::Stderr(bytes) => stderr.extend_from_slice(&bytes),
+            },
+        );
+    // malformed output would only come back malformed on a fresh session
+    if let Some(e) = parse_error {
+        return Err(RemoteError::bad_output(command, format!("{e:#}")).into());
+    }
+    let status = status?;
+    let stderr = String::from_utf8_lossy(&stderr).into_owned();
+    // find keeps going past unreadable directories and exits with 1 at the end
+    if status.code == 1 && status.signal.is_none() {
+        errors.extend(stderr.lines().map(String::from));
+    } else {
+        CommandOutput {
+            stdout: Vec::new(),
+            stderr,
+            exit_code: status.code,
+            signal: status.signal,
+            duration: Duration::ZERO,
+        }
+        .check(command)?;
     }
+    parser
+        .finish()
+        .map_err(|e| RemoteError::bad_output(command, format!("{e:#}")))?;
+    Ok(builder.finish())
 }
 
+/// Result of [`RemoteFileOperations::disk_usage`]
 #[derive(Debug, Clone)]
+pub struct DiskUsage {
+    pub root: DirUsage,
+    /// Paths that could not be read, so the totals are too low
+    pub errors: Vec<String>,
+}
+
+#[derive(Debug, Clone, Default)]
 pub struct FileInfo {
     pub name: String,
     pub path: String,
@@ -157,14 +1108,201 @@ pub struct FileInfo {
     pub is_symlink: bool,
     pub modified_time: u64, // Unix timestamp
     pub permissions: String,
+    pub uid: Option<u32>,
+    pub gid: Option<u32>,
+    /// `st_mode`, file type bits included
+    pub mode: Option<u32>,
+    pub owner: Option<String>,
+    pub group: Option<String>,
+    pub inode: Option<u64>,
+    /// Device the entry lives on, which with `inode` identifies the file
+    pub dev: Option<u64>,
+    /// Hard links, more than one for a file means transferring it may duplicate data
+    pub nlink: Option<u64>,
+    pub symlink_target: Option<PathBuf>,
+    /// The exact path, which `path` only approximates when it is not UTF-8
+    pub raw_path: PathBuf,
+}
+
+impl FileInfo {
+    /// What kind of entry this is, from the mode where known and the `ls` type
+    /// character otherwise
+    pub fn kind(&self) -> FileKind {
+        if self.is_directory {
+            FileKind::Directory
+        } else if self.is_symlink {
+            FileKind::Symlink
+        } else if self
+            .mode
+            .map_or(self.permissions.starts_with(['c', 'b', 'p', 's']), |mode| {
+                mode & 0o170000 != 0o100000
+            })
+        {
+            FileKind::Special
+        } else {
+            FileKind::File
+        }
+    }
+
+    fn from_sftp(path: &Path, stat: &FileStat) -> Self {
+        let file_type = stat.file_type();
+        FileInfo {
+            name: path
+                .file_name()
+                .unwrap_or(path.as_os_str())
+                .to_string_lossy()
+                .into_owned(),
+            path: path.to_string_lossy().into_owned(),
+            raw_path: path.to_path_buf(),
+            size: stat.size.unwrap_or(0),
+            is_directory: file_type.is_dir(),
+            is_symlink: file_type.is_symlink(),
+            modified_time: stat.mtime.unwrap_or(0),
+            permissions: stat.perm.map(mode_string).unwrap_or_default(),
+            uid: stat.uid,
+            gid: stat.gid,
+            mode: stat.perm,
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FileKind {
+    File,
+    Directory,
+    Symlink,
+    /// Devices, FIFOs and sockets
+    Special,
+}
+
+/// A directory's entries sorted by kind
+#[derive(Debug, Clone, Default)]
+pub struct Listing {
+    pub files: Vec<FileInfo>,
+    pub directories: Vec<FileInfo>,
+    pub symlinks: Vec<FileInfo>,
+    pub specials: Vec<FileInfo>,
+}
+
+impl Listing {
+    pub fn len(&self) -> usize {
+        self.files.len() + self.directories.len() + self.symlinks.len() + self.specials.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl FromIterator<FileInfo> for Listing {
+    fn from_iter<I: IntoIterator<Item = FileInfo>>(entries: I) -> Self {
+        let mut listing = Listing::default();
+        for entry in entries {
+            match entry.kind() {
+                FileKind::File => listing.files.push(entry),
+                FileKind::Directory => listing.directories.push(entry),
+                FileKind::Symlink => listing.symlinks.push(entry),
+                FileKind::Special => listing.specials.push(entry),
+            }
+        }
+        listing
+    }
+}
+
+/// Read back the mode from its `ls -l` form, ignoring any ACL or xattr marker
+fn parse_mode_string(rendered: &str) -> Option<u32> {
+    let chars: Vec<char> = rendered.chars().take(10).collect();
+    if chars.len() != 10 {
+        return None;
+    }
+    let mut mode = match chars[0] {
+        'd' => 0o040000,
+        'l' => 0o120000,
+        'c' => 0o020000,
+        'b' => 0o060000,
+        'p' => 0o010000,
+        's' => 0o140000,
+        '-' => 0o100000,
+        _ => return None,
+    };
+    for (i, (shift, special)) in [(6, 0o4000), (3, 0o2000), (0, 0o1000)]
+        .into_iter()
+        .enumerate()
+    {
+        let [r, w, x] = [chars[1 + i * 3], chars[2 + i * 3], chars[3 + i * 3]];
+        if r == 'r' {
+            mode |= 0o4 << shift;
+        }
+        if w == 'w' {
+            mode |= 0o2 << shift;
+        }
+        match x {
+            'x' => mode |= 0o1 << shift,
+            's' | 't' => mode |= (0o1 << shift) | special,
+            'S' | 'T' => mode |= special,
+            _ => {}
+        }
+    }
+    Some(mode)
+}
+
+/// Render `st_mode` the way `ls -l` does, e.g. `drwxr-sr-x`
+pub(crate) fn mode_string(mode: u32) -> String {
+    let file_type = match mode & 0o170000 {
+        0o040000 => 'd',
+        0o120000 => 'l',
+        0o020000 => 'c',
+        0o060000 => 'b',
+        0o010000 => 'p',
+        0o140000 => 's',
+        _ => '-',
+    };
+    let mut rendered = String::from(file_type);
+    for (shift, special, set_char) in [(6, 0o4000, 's'), (3, 0o2000, 's'), (0, 0o1000, 't')] {
+        let bits = (mode >> shift) & 0o7;
+        rendered.push(if bits & 0o4 != 0 { 'r' } else { '-' });
+        rendered.push(if bits & 0o2 != 0 { 'w' } else { '-' });
+        rendered.push(match (bits & 0o1 != 0, mode & special != 0) {
+            (true, true) => set_char,
+            (false, true) => set_char.to_ascii_uppercase(),
+            (true, false) => 'x',
+            (false, false) => '-',
+        });
+    }
+    rendered
+}
+
+/// Paths printed with a NUL after each
+fn nul_separated(output: &[u8]) -> impl Iterator<Item = PathBuf> + '_ {
+    output
+        .split(|&b| b == 0)
+        .filter(|path| !path.is_empty())
+        .map(|path| PathBuf::from(OsStr::from_bytes(path)))
+}
+
+/// `ls -lA` in the C locale, so month names are English, with full timestamps where
+/// the host's ls supports them
+fn ls_command(capabilities: &Capabilities, path: &Path) -> RemoteCommand {
+    let command = RemoteCommand::new("env").args(["LC_ALL=C", "ls", "-lA"]);
+    // GNU coreutils also means GNU ls
+    let command = if capabilities.stat == StatFlavor::Gnu {
+        command.arg("--time-style=full-iso")
+    } else {
+        command
+    };
+    command.path(path)
 }
 
 /// Parse ls -lA output into FileInfo structs
-fn parse_ls_output(output: &str) -> Vec<FileInfo> {
+pub(crate) fn parse_ls_output(output: &str) -> Vec<FileInfo> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |d| d.as_secs());
     let mut files = Vec::new();
 
     for line in output.lines() {
-        if let Some(file_info) = parse_ls_line(line) {
+        if let Some(file_info) = parse_ls_line_at(line, now) {
             files.push(file_info);
         }
     }
@@ -173,15 +1311,32 @@ fn parse_ls_output(output: &str) -> Vec<FileInfo> {
 }
 
 /// Parse a single line from ls -lA output
+#[cfg(test)]
 fn parse_ls_line(line: &str) -> Option<FileInfo> {
-    let parts: Vec<&str> = line.split_whitespace().collect();
+    parse_ls_line_at(line, 1_704_110_400) // 2024-01-01 12:00 UTC
+}
+
+/// Parse a single line from ls -lA output, `now` placing year-less dates
+fn parse_ls_line_at(line: &str, now: u64) -> Option<FileInfo> {
+    let mut parts: Vec<&str> = line.split_whitespace().collect();
+    // devices show `major, minor` where the size would be
+    if parts.len() > 5 && parts[4].ends_with(',') {
+        parts.remove(5);
+    }
     if parts.len() < 9 {
         return None;
     }
 
     let permissions = parts[0].to_string();
     let size_str = parts[4];
-    let name = parts[8..].join(" "); // Handle filenames with spaces
+    let modified_time = parse_ls_time(&parts[5..8], now).unwrap_or(0);
+    let mut name = parts[8..].join(" "); // Handle filenames with spaces
+    let is_symlink = permissions.starts_with('l');
+    let mut symlink_target = None;
+    if is_symlink && let Some((link, target)) = name.split_once(" -> ") {
+        symlink_target = Some(PathBuf::from(target));
+        name = link.to_string();
+    }
 
     // Skip . and .. entries
     if name == "." || name == ".." {
@@ -190,57 +1345,179 @@ fn parse_ls_line(line: &str) -> Option<FileInfo> {
 
     let size = size_str.parse().unwrap_or(0);
     let is_directory = permissions.starts_with('d');
-    let is_symlink = permissions.starts_with('l');
+    let mode = parse_mode_string(&permissions);
 
     Some(FileInfo {
+        raw_path: PathBuf::from(&name),
         name: name.clone(),
         path: name,
         size,
         is_directory,
         is_symlink,
-        modified_time: 0, // Would need to parse date from ls output
+        modified_time,
         permissions,
+        mode,
+        owner: Some(parts[2].to_string()),
+        group: Some(parts[3].to_string()),
+        nlink: parts[1].parse().ok(),
+        symlink_target,
+        ..Default::default()
     })
 }
 
+/// The three timestamp columns of `ls -l` as a Unix timestamp. Understands
+/// `--time-style=full-iso` (`2024-01-15 12:34:56.123456789 +0100`) and the C locale's
+/// `Jan 15 12:34` and `Jan 15  2023`. The latter two are in the remote host's local
+/// time, which is taken to be UTC.
+fn parse_ls_time(fields: &[&str], now: u64) -> Option<u64> {
+    let [first, second, third] = fields else {
+        return None;
+    };
+
+    if let Some((year, rest)) = first.split_once('-') {
+        let (month, day) = rest.split_once('-')?;
+        let time = second.split('.').next()?;
+        let seconds = time_of_day(time)?;
+        let offset = utc_offset(third)?;
+        let local = days_from_civil(year.parse().ok()?, month.parse().ok()?, day.parse().ok()?)
+            * 86_400
+            + seconds;
+        return u64::try_from(local - offset).ok();
+    }
+
+    let month = MONTHS.iter().position(|m| m == first)? as u32 + 1;
+    let day: u32 = second.parse().ok()?;
+    let timestamp = |year: i64, seconds: i64| {
+        u64::try_from(days_from_civil(year, month, day) * 86_400 + seconds).ok()
+    };
+    if third.contains(':') {
+        // recent files leave out the year, ls shows those up to six months old
+        let seconds = time_of_day(third)?;
+        let this_year = civil_year(now);
+        let guess = timestamp(this_year, seconds)?;
+        // allow for clock skew before deciding it must be from last year
+        if guess > now + 86_400 {
+            timestamp(this_year - 1, seconds)
+        } else {
+            Some(guess)
+        }
+    } else {
+        timestamp(third.parse().ok()?, 0)
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// `HH:MM` or `HH:MM:SS` in seconds
+fn time_of_day(time: &str) -> Option<i64> {
+    let mut parts = time.split(':').map(|part| part.parse::<i64>().ok());
+    let hours = parts.next()??;
+    let minutes = parts.next()??;
+    let seconds = parts.next().unwrap_or(Some(0))?;
+    Some(hours * 3600 + minutes * 60 + seconds)
+}
+
+/// `+0100` in seconds east of UTC
+fn utc_offset(offset: &str) -> Option<i64> {
+    let (sign, digits) = offset.split_at_checked(1)?;
+    let sign = match sign {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let hours: i64 = digits.get(..2)?.parse().ok()?;
+    let minutes: i64 = digits.get(2..4)?.parse().ok()?;
+    Some(sign * (hours * 3600 + minutes * 60))
+}
+
+/// Days since 1970-01-01 of a proleptic Gregorian date
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = i64::from(month);
+    let day_of_year = (153 * (month + if month > 2 { -3 } else { 9 }) + 2) / 5 + i64::from(day) - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// The UTC year a Unix timestamp falls in
+fn civil_year(timestamp: u64) -> i64 {
+    let days = (timestamp / 86_400) as i64;
+    // start from an estimate and correct it, the estimate is at most a year off
+    let mut year = 1970 + days / 365;
+    while days_from_civil(year, 1, 1) > days {
+        year -= 1;
+    }
+    while days_from_civil(year + 1, 1, 1) <= days {
+        year += 1;
+    }
+    year
+}
+
+/// `stat` flag and format printing the fields [`parse_stat_output`] expects, `|`
+/// separated: mode in hex, size, mtime,
+/// inode, links, uid, gid, owner, group and name. The name is the one free-form
+/// field and comes last, so a `|` in it survives. Link targets are read separately,
+/// as GNU stat has no format for them.
+fn stat_format(flavor: StatFlavor) -> (&'static str, &'static str) {
+    match flavor {
+        StatFlavor::Bsd => ("-f", "%Xp|%z|%m|%i|%l|%u|%g|%Su|%Sg|%N"),
+        StatFlavor::Gnu | StatFlavor::Unknown => ("-c", "%f|%s|%Y|%i|%h|%u|%g|%U|%G|%n"),
+    }
+}
+
 /// Parse stat command output
 fn parse_stat_output(output: &str) -> Result<FileInfo> {
-    let parts: Vec<&str> = output.split('|').collect();
-    if parts.len() != 4 {
+    let parts: Vec<&str> = output.splitn(10, '|').collect();
+    if parts.len() != 10 {
         return Err(eyre!("Invalid stat output format"));
     }
 
-    let file_type = parts[0];
+    let mode = u32::from_str_radix(parts[0], 16).wrap_err("Invalid mode in stat output")?;
     let size: u64 = parts[1].parse().wrap_err("Invalid size in stat output")?;
     let modified_time: u64 = parts[2]
         .parse()
         .wrap_err("Invalid timestamp in stat output")?;
-    let name = parts[3].to_string();
+    let name = parts[9].to_string();
 
     Ok(FileInfo {
+        raw_path: PathBuf::from(&name),
         name: name.clone(),
         path: name,
         size,
-        is_directory: file_type.contains("directory"),
-        is_symlink: file_type.contains("symbolic link"),
+        is_directory: mode & 0o170000 == 0o040000,
+        is_symlink: mode & 0o170000 == 0o120000,
         modified_time,
-        permissions: String::new(), // stat doesn't include permissions in this format
+        permissions: mode_string(mode),
+        uid: Some(stat_number(parts[5], "uid")?),
+        gid: Some(stat_number(parts[6], "gid")?),
+        mode: Some(mode),
+        owner: Some(parts[7].to_string()),
+        group: Some(parts[8].to_string()),
+        inode: Some(stat_number(parts[3], "inode")?),
+        dev: None,
+        nlink: Some(stat_number(parts[4], "link count")?),
+        // read separately by the caller
+        symlink_target: None,
     })
 }
 
+fn stat_number<T: FromStr>(field: &str, what: &str) -> Result<T> {
+    field
+        .parse()
+        .map_err(|_| eyre!("Invalid {what} in stat output"))
+}
+
 /// Example usage
 pub fn example_usage() -> Result<()> {
-    // Connect to remote host (this part you'd implement based on your connection logic)
-    let tcp = TcpStream::connect("example.com:22")?;
-    let mut session = Session::new()?;
-    session.set_tcp_stream(tcp);
-    session.handshake()?;
-
-    // Authenticate (implement your auth logic)
-    // session.userauth_password("username", "password")?;
-
-    // Use the remote operations
-    let remote_ops = RemoteFileOperations::new(&session);
+    // Sessions are opened by the pool on first use
+    let pool = ConnectionPool::default();
+    let profile = ConnectionProfile::new("example.com", "user");
+    // Use the remote operations, reconnecting if the session drops
+    let remote_ops = RemoteFileOperations::pooled(&pool, &profile);
 
     // Check if rsync is available
     if remote_ops.check_rsync_available()? {
@@ -264,6 +1541,133 @@ pub fn example_usage() -> Result<()> {
 mod tests {
     use super::*;
 
+    #[tokio::test]
+    async fn test_execute_async_reports_connect_failure() {
+        let pool =
+            ConnectionPool::new(1).reconnect_policy(crate::reconnect::ReconnectPolicy::none());
+        let profile = ConnectionProfile::new("127.0.0.1", "transfer")
+            .port(1)
+            .connect_timeout(Duration::from_millis(200));
+        let result = execute_remote_command_async(
+            &pool,
+            &profile,
+            "true",
+            Some(1),
+            CancellationToken::new(),
+        )
+        .await;
+        assert!(matches!(result, Err(RemoteError::Connect(_))));
+        assert_eq!(pool.in_use(&profile), 0);
+    }
+
+    #[test]
+    fn test_line_buffer_splits_across_chunks() {
+        let mut buffer = LineBuffer::default();
+        let mut lines = Vec::new();
+        buffer.push(b"first\nsec", |l| lines.push(l));
+        buffer.push(b"ond\r\n\nthi", |l| lines.push(l));
+        assert_eq!(lines, vec!["first", "second", ""]);
+        buffer.finish(|l| lines.push(l));
+        assert_eq!(lines, vec!["first", "second", "", "thi"]);
+    }
+
+    #[tokio::test]
+    async fn test_stream_async_reports_connect_failure() {
+        let pool = ConnectionPool::new(1);
+        let profile = ConnectionProfile::new("127.0.0.1", "transfer")
+            .port(1)
+            .connect_timeout(Duration::from_millis(200));
+        let mut stream =
+            stream_remote_command_async(&pool, &profile, "true", None, CancellationToken::new());
+        assert_eq!(stream.next_line().await, None);
+        assert!(stream.finish().await.is_err());
+    }
+
+    #[test]
+    fn test_fixed_session_is_claimed_exclusively() -> Result<()> {
+        let session = Session::new()?;
+        let ops = RemoteFileOperations::new(&session);
+        let claim = ops.claim()?;
+        assert!(ops.with_session(|_| Ok(())).is_err());
+        drop(claim);
+        ops.with_session(|_| Ok(()))
+    }
+
+    #[tokio::test]
+    async fn test_stream_finish_drains_unread_lines() {
+        let stream = spawn_stream(CancellationToken::new(), |cancel, on_line| {
+            for i in 0..STREAM_BUFFER_LINES * 3 {
+                on_line(OutputLine::Stdout(i.to_string()));
+            }
+            assert!(!cancel.is_cancelled());
+            Ok(ExitStatus {
+                code: 0,
+                signal: None,
+            })
+        });
+        assert_eq!(stream.finish().await.unwrap().code, 0);
+    }
+
+    #[tokio::test]
+    async fn test_dropping_quiet_stream_cancels() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let stream = spawn_stream(CancellationToken::new(), move |cancel, _| {
+            // a command that prints nothing, like rsync while it scans
+            while !cancel.is_cancelled() {
+                thread::sleep(POLL_INTERVAL);
+            }
+            tx.send(()).unwrap();
+            Err(RemoteError::Cancelled {
+                command: String::from("rsync"),
+            })
+        });
+        drop(stream);
+        rx.recv_timeout(Duration::from_secs(5)).unwrap();
+    }
+
+    #[test]
+    fn test_retry_gives_up_at_deadline() {
+        let deadline = Some(Instant::now() + Duration::from_millis(50));
+        let mut calls = 0;
+        let result: Result<(), Stop> = retry(deadline, &CancellationToken::new(), "", || {
+            calls += 1;
+            // LIBSSH2_ERROR_EAGAIN
+            Err(ssh2::Error::from_errno(ssh2::ErrorCode::Session(-37)))
+        });
+        assert!(matches!(result, Err(Stop::TimedOut)));
+        assert!(calls > 1);
+
+        let cancel = CancellationToken::new();
+        cancel.cancel();
+        let result = retry(None, &cancel, "", || Ok(()));
+        assert!(matches!(result, Err(Stop::Cancelled)));
+    }
+
+    #[test]
+    fn test_command_output_check() {
+        let output = CommandOutput {
+            stdout: b"ok\n".to_vec(),
+            stderr: String::new(),
+            exit_code: 0,
+            signal: None,
+            duration: Duration::from_millis(5),
+        };
+        assert_eq!(output.stdout_lossy(), "ok\n");
+        assert!(output.clone().check("true").is_ok());
+
+        let killed = CommandOutput {
+            signal: Some(String::from("KILL")),
+            ..output
+        };
+        assert!(matches!(
+            killed.check("sleep 60"),
+            Err(RemoteError::NonZeroExit {
+                signal: Some(_),
+                ..
+            })
+        ));
+    }
+
     #[test]
     fn test_parse_ls_line() {
         let line = "-rw-r--r-- 1 user group 1024 Jan 1 12:00 test.txt";
@@ -275,6 +1679,17 @@ mod tests {
         assert!(!file_info.is_symlink);
     }
 
+    #[test]
+    fn test_parse_ls_line_ownership() {
+        let line = "-rw-r----- 3 alice staff 1024 Jan 1 12:00 shared.bin";
+        let file_info = parse_ls_line(line).unwrap();
+
+        assert_eq!(file_info.owner.as_deref(), Some("alice"));
+        assert_eq!(file_info.group.as_deref(), Some("staff"));
+        assert_eq!(file_info.nlink, Some(3));
+        assert_eq!(file_info.mode, Some(0o100640));
+    }
+
     #[test]
     fn test_parse_ls_line_directory() {
         let line = "drwxr-xr-x 2 user group 4096 Jan 1 12:00 mydir";
@@ -284,13 +1699,210 @@ mod tests {
         assert!(file_info.is_directory);
     }
 
+    #[test]
+    fn test_from_sftp() {
+        let stat = FileStat {
+            size: Some(4096),
+            uid: Some(1000),
+            gid: Some(100),
+            perm: Some(0o042755),
+            atime: None,
+            mtime: Some(1_700_000_000),
+        };
+        let file_info = FileInfo::from_sftp(Path::new("/data/two  spaces"), &stat);
+        assert_eq!(file_info.name, "two  spaces");
+        assert_eq!(file_info.path, "/data/two  spaces");
+        assert!(file_info.is_directory);
+        assert_eq!(file_info.modified_time, 1_700_000_000);
+        assert_eq!(file_info.permissions, "drwxr-sr-x");
+        assert_eq!(file_info.uid, Some(1000));
+    }
+
+    #[test]
+    fn test_mode_string() {
+        assert_eq!(mode_string(0o100644), "-rw-r--r--");
+        assert_eq!(mode_string(0o120777), "lrwxrwxrwx");
+        assert_eq!(mode_string(0o041777), "drwxrwxrwt");
+        assert_eq!(mode_string(0o104754), "-rwsr-xr--");
+        assert_eq!(mode_string(0o102640), "-rw-r-S---");
+    }
+
+    #[test]
+    fn test_parse_ls_times() {
+        let now = 1_704_110_400; // 2024-01-01 12:00 UTC
+        let time = |line: &str| parse_ls_line_at(line, now).unwrap().modified_time;
+
+        let iso = "-rw-r--r-- 1 u g 10 2023-06-15 14:30:05.123456789 +0200 a b";
+        assert_eq!(time(iso), 1_686_832_205);
+        assert_eq!(parse_ls_line_at(iso, now).unwrap().name, "a b");
+        // earlier today, and a date that must be last year's
+        assert_eq!(time("-rw-r--r-- 1 u g 10 Jan  1 09:15 a"), 1_704_100_500);
+        assert_eq!(time("-rw-r--r-- 1 u g 10 Dec 31 23:59 a"), 1_704_067_140);
+        assert_eq!(time("-rw-r--r-- 1 u g 10 Feb 29  2020 a"), 1_582_934_400);
+
+        let device = parse_ls_line_at("crw-rw---- 1 root disk 8, 0 Jan  1 09:15 sda", now);
+        assert_eq!(device.unwrap().name, "sda");
+    }
+
+    #[test]
+    fn test_ls_command_requests_stable_format() {
+        let gnu = Capabilities {
+            stat: StatFlavor::Gnu,
+            ..Default::default()
+        };
+        assert_eq!(
+            ls_command(&gnu, Path::new("/data")).as_str(),
+            "env 'LC_ALL=C' ls -lA '--time-style=full-iso' -- /data"
+        );
+        assert_eq!(
+            ls_command(&Capabilities::default(), Path::new("/data")).as_str(),
+            "env 'LC_ALL=C' ls -lA -- /data"
+        );
+    }
+
+    /// Run a generated command with the local `sh` under strict POSIX option parsing
+    fn run_posix(command: RemoteCommand) -> std::process::Output {
+        std::process::Command::new("sh")
+            .arg("-c")
+            .arg(command.as_str())
+            .env("POSIXLY_CORRECT", "1")
+            .output()
+            .unwrap()
+    }
+
+    #[test]
+    fn test_chmod_chown_posixly_correct() -> Result<()> {
+        use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+        let dir = tempfile::TempDir::new()?;
+        let path = dir.path().join("-file");
+        std::fs::write(&path, b"")?;
+
+        let command = chmod_command(&path, 0o600);
+        assert!(command.as_str().starts_with("chmod -- 600 "));
+        let output = run_posix(command);
+        assert!(output.status.success(), "{output:?}");
+        assert_eq!(
+            std::fs::metadata(&path)?.permissions().mode() & 0o7777,
+            0o600
+        );
+
+        let meta = std::fs::metadata(&path)?;
+        let (uid, gid) = (meta.uid().to_string(), meta.gid().to_string());
+        let command = chown_command(&path, Some(&uid), Some(&gid)).unwrap();
+        let output = run_posix(command);
+        assert!(output.status.success(), "{output:?}");
+        assert!(chown_command(&path, None, None).is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_check_deletable() {
+        let home = Path::new("/home/alice");
+        for path in ["/data/run_1", "/home/alice/runs", "/var/lib/app/cache"] {
+            assert!(check_deletable(Path::new(path), home).is_ok(), "{path}");
+        }
+        for path in ["/", "/home", "/data/..", "data/run_1", ""] {
+            assert!(
+                matches!(
+                    check_deletable(Path::new(path), home),
+                    Err(RemoteError::Refused(_))
+                ),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_check_deletable_protects_home_and_system() {
+        let home = Path::new("/export/home/alice");
+        for path in [
+            "/export/home/alice",
+            "/export/home",
+            "/var/lib",
+            "/var/log",
+            "/usr/local/lib",
+            "/etc/ssh",
+        ] {
+            assert!(
+                matches!(
+                    check_deletable(Path::new(path), home),
+                    Err(RemoteError::Refused(_))
+                ),
+                "{path}"
+            );
+        }
+        assert!(check_deletable(Path::new("/export/home/bob/runs"), home).is_ok());
+    }
+
+    #[test]
+    fn test_split_tree_root() {
+        assert_eq!(
+            split_tree_root(Path::new("/data/latest/")).unwrap(),
+            (Path::new("/data"), OsStr::new("latest"))
+        );
+        assert_eq!(
+            split_tree_root(Path::new("runs/../latest")).unwrap(),
+            (Path::new("runs/.."), OsStr::new("latest"))
+        );
+        assert_eq!(
+            split_tree_root(Path::new("latest")).unwrap(),
+            (Path::new("."), OsStr::new("latest"))
+        );
+        for path in ["/data/run_1/..", "/", "."] {
+            assert!(
+                matches!(
+                    split_tree_root(Path::new(path)),
+                    Err(RemoteError::Refused(_))
+                ),
+                "{path}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_symlink_tree_root_refused() {
+        let mut link =
+            parse_stat_output("a1ff|15|1640995200|393218|1|1000|100|alice|users|latest").unwrap();
+        link.raw_path = PathBuf::from("/data/latest");
+        assert!(matches!(
+            check_not_symlink(&link),
+            Err(RemoteError::Refused(_))
+        ));
+        let dir =
+            parse_stat_output("41ed|4096|1640995200|393219|2|1000|100|alice|users|run_1").unwrap();
+        assert!(check_not_symlink(&dir).is_ok());
+    }
+
     #[test]
     fn test_parse_stat_output() {
-        let output = "regular file|1024|1640995200|test.txt";
+        let output = "81a4|1024|1640995200|393217|2|1000|100|alice|users|test.txt";
         let file_info = parse_stat_output(output).unwrap();
 
         assert_eq!(file_info.name, "test.txt");
         assert_eq!(file_info.size, 1024);
         assert!(!file_info.is_directory);
+        assert_eq!(file_info.permissions, "-rw-r--r--");
+        assert_eq!(file_info.inode, Some(393217));
+        assert_eq!(file_info.nlink, Some(2));
+        assert_eq!(file_info.owner.as_deref(), Some("alice"));
+        assert_eq!(file_info.symlink_target, None);
+
+        // BSD stat, with a `|` in the name
+        let output = "a1ed|9|1640995200|12|1|0|0|root|wheel|a|b";
+        let file_info = parse_stat_output(output).unwrap();
+        assert_eq!(file_info.name, "a|b");
+        assert!(file_info.is_symlink);
+        assert_eq!(file_info.uid, Some(0));
+        assert!(parse_stat_output("81a4|1024|1640995200").is_err());
+    }
+
+    #[test]
+    fn test_parse_mode_string() {
+        for mode in [0o100644, 0o040755, 0o120777, 0o041777, 0o104754, 0o102640] {
+            assert_eq!(parse_mode_string(&mode_string(mode)), Some(mode));
+        }
+        assert_eq!(parse_mode_string("-rw-r--r--+"), Some(0o100644));
+        assert_eq!(parse_mode_string("total"), None);
     }
 }
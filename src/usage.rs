@@ -0,0 +1,319 @@
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, BinaryHeap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use crate::tx_ssh::FileInfo;
+
+/// Space used below one directory, with a node per subdirectory
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DirUsage {
+    pub path: PathBuf,
+    /// Bytes in this directory and everything below it
+    pub bytes: u64,
+    /// Files in this directory and everything below it
+    pub files: u64,
+    /// Largest files anywhere below, biggest first
+    pub largest: Vec<LargeFile>,
+    pub children: Vec<DirUsage>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LargeFile {
+    pub path: PathBuf,
+    pub size: u64,
+}
+
+/// Column to order a [`DirUsage`] breakdown by
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UsageSort {
+    /// Largest first
+    Bytes,
+    /// Most files first
+    Files,
+    Name,
+}
+
+impl DirUsage {
+    /// Order the children at every level
+    pub fn sort_by(&mut self, sort: UsageSort) {
+        match sort {
+            UsageSort::Bytes => self.children.sort_by_key(|child| Reverse(child.bytes)),
+            UsageSort::Files => self.children.sort_by_key(|child| Reverse(child.files)),
+            UsageSort::Name => self.children.sort_by(|a, b| a.path.cmp(&b.path)),
+        }
+        for child in &mut self.children {
+            child.sort_by(sort);
+        }
+    }
+}
+
+/// Roll the entries of a recursive listing of `root` up into per-directory totals,
+/// keeping the `largest` biggest files at each level. Symlinks are not counted, and
+/// a file with several hard links only once where device and inode are known.
+pub fn summarize(
+    root: &Path,
+    entries: impl IntoIterator<Item = FileInfo>,
+    largest: usize,
+) -> DirUsage {
+    let mut builder = UsageBuilder::new(root, largest);
+    for entry in entries {
+        builder.add(entry);
+    }
+    builder.finish()
+}
+
+/// [`summarize`] one entry at a time. Memory grows with the number of directories,
+/// each keeping its totals and at most `largest` files, and with the number of files
+/// that have more than one hard link.
+pub struct UsageBuilder {
+    root: PathBuf,
+    largest: usize,
+    /// Own files of each directory, keyed by path so parents sort before children
+    dirs: BTreeMap<PathBuf, Node>,
+    /// `(device, inode)` of every multi-link file counted so far
+    seen_inodes: HashSet<(u64, u64)>,
+}
+
+#[derive(Default)]
+struct Node {
+    usage: DirUsage,
+    top: TopFiles,
+}
+
+/// The biggest files seen so far, smallest on top so it is the one evicted
+#[derive(Default)]
+struct TopFiles(BinaryHeap<Reverse<(u64, Reverse<PathBuf>)>>);
+
+impl TopFiles {
+    fn push(&mut self, size: u64, path: PathBuf, keep: usize) {
+        if keep == 0 {
+            return;
+        }
+        // on equal sizes the earlier path is kept
+        self.0.push(Reverse((size, Reverse(path))));
+        if self.0.len() > keep {
+            self.0.pop();
+        }
+    }
+
+    fn sorted(&self) -> Vec<LargeFile> {
+        let mut files: Vec<LargeFile> = self
+            .0
+            .iter()
+            .map(|Reverse((size, Reverse(path)))| LargeFile {
+                path: path.clone(),
+                size: *size,
+            })
+            .collect();
+        files.sort_by(|a, b| b.size.cmp(&a.size).then_with(|| a.path.cmp(&b.path)));
+        files
+    }
+}
+
+impl UsageBuilder {
+    pub fn new(root: &Path, largest: usize) -> Self {
+        let mut dirs = BTreeMap::new();
+        dirs.insert(root.to_path_buf(), node(root));
+        Self {
+            root: root.to_path_buf(),
+            largest,
+            dirs,
+            seen_inodes: HashSet::new(),
+        }
+    }
+
+    pub fn add(&mut self, entry: FileInfo) {
+        let path = entry.raw_path;
+        if !path.starts_with(&self.root) {
+            return;
+        }
+        if entry.is_directory {
+            self.dirs.entry(path.clone()).or_insert_with(|| node(&path));
+            return;
+        }
+        if entry.is_symlink {
+            return;
+        }
+        // inode numbers are only unique within one device
+        if entry.nlink.unwrap_or(1) > 1
+            && let (Some(dev), Some(inode)) = (entry.dev, entry.inode)
+            && !self.seen_inodes.insert((dev, inode))
+        {
+            return;
+        }
+        let Some(parent) = path.parent() else {
+            return;
+        };
+        let dir = self
+            .dirs
+            .entry(parent.to_path_buf())
+            .or_insert_with(|| node(parent));
+        dir.usage.bytes += entry.size;
+        dir.usage.files += 1;
+        dir.top.push(entry.size, path, self.largest);
+    }
+
+    pub fn finish(mut self) -> DirUsage {
+        let root = self.root;
+        // directories only seen through their files still need a chain up to the root
+        let missing: Vec<PathBuf> = self
+            .dirs
+            .keys()
+            .flat_map(|dir| {
+                dir.ancestors()
+                    .skip(1)
+                    .take_while(|ancestor| ancestor.starts_with(&root))
+            })
+            .map(Path::to_path_buf)
+            .collect();
+        for dir in missing {
+            self.dirs.entry(dir.clone()).or_insert_with(|| node(&dir));
+        }
+
+        // fold children into parents, which sort before them
+        while let Some((path, mut dir)) = self.dirs.pop_last() {
+            dir.usage.largest = dir.top.sorted();
+            // they were popped in reverse
+            dir.usage.children.reverse();
+            if path == root {
+                return dir.usage;
+            }
+            let parent = path
+                .parent()
+                .and_then(|parent| self.dirs.get_mut(parent))
+                .expect("every directory below the root has its parent");
+            parent.usage.bytes += dir.usage.bytes;
+            parent.usage.files += dir.usage.files;
+            for file in &dir.usage.largest {
+                parent.top.push(file.size, file.path.clone(), self.largest);
+            }
+            parent.usage.children.push(dir.usage);
+        }
+        unreachable!("the root is never removed before the end")
+    }
+}
+
+fn node(path: &Path) -> Node {
+    Node {
+        usage: DirUsage {
+            path: path.to_path_buf(),
+            ..Default::default()
+        },
+        top: TopFiles::default(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn file(path: &str, size: u64) -> FileInfo {
+        FileInfo {
+            raw_path: PathBuf::from(path),
+            size,
+            ..Default::default()
+        }
+    }
+
+    fn dir(path: &str) -> FileInfo {
+        FileInfo {
+            raw_path: PathBuf::from(path),
+            is_directory: true,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_summarize_rolls_up() {
+        let hard_link = |path| FileInfo {
+            inode: Some(7),
+            dev: Some(2049),
+            nlink: Some(2),
+            ..file(path, 500)
+        };
+        let entries = vec![
+            file("/data/top.txt", 10),
+            dir("/data/runs"),
+            dir("/data/empty"),
+            file("/data/runs/run_1/a.bin", 1000),
+            file("/data/runs/run_1/b.bin", 200),
+            file("/data/runs/run_2/c.bin", 300),
+            hard_link("/data/runs/run_2/d.bin"),
+            hard_link("/data/runs/run_2/d-again.bin"),
+            FileInfo {
+                is_symlink: true,
+                ..file("/data/latest", 20)
+            },
+        ];
+        let mut usage = summarize(Path::new("/data"), entries, 2);
+
+        assert_eq!(usage.bytes, 2010);
+        assert_eq!(usage.files, 5);
+        assert_eq!(
+            usage.largest,
+            vec![
+                LargeFile {
+                    path: PathBuf::from("/data/runs/run_1/a.bin"),
+                    size: 1000,
+                },
+                LargeFile {
+                    path: PathBuf::from("/data/runs/run_2/d.bin"),
+                    size: 500,
+                },
+            ]
+        );
+
+        usage.sort_by(UsageSort::Bytes);
+        assert_eq!(usage.children.len(), 2);
+        let runs = &usage.children[0];
+        assert_eq!(runs.path, Path::new("/data/runs"));
+        assert_eq!((runs.bytes, runs.files), (2000, 4));
+        assert_eq!(runs.children[0].path, Path::new("/data/runs/run_1"));
+        assert_eq!(usage.children[1].bytes, 0);
+
+        usage.sort_by(UsageSort::Name);
+        assert_eq!(usage.children[0].path, Path::new("/data/empty"));
+        usage.sort_by(UsageSort::Files);
+        assert_eq!(usage.children[0].path, Path::new("/data/runs"));
+    }
+
+    #[test]
+    fn test_summarize_keeps_no_files_for_zero_largest() {
+        let entries = (0..100).map(|i| file(&format!("/data/run/{i}.bin"), i));
+        let usage = summarize(Path::new("/data"), entries, 0);
+        assert_eq!(usage.files, 100);
+        assert!(usage.largest.is_empty());
+        assert!(usage.children[0].largest.is_empty());
+
+        let entries = (0..100).map(|i| file(&format!("/data/run/{i}.bin"), i % 10));
+        let usage = summarize(Path::new("/data"), entries, 2);
+        let sizes: Vec<u64> = usage.largest.iter().map(|f| f.size).collect();
+        assert_eq!(sizes, [9, 9]);
+        assert_eq!(usage.largest[0].path, Path::new("/data/run/19.bin"));
+    }
+
+    #[test]
+    fn test_summarize_same_inode_on_other_device() {
+        let linked = |path, dev| FileInfo {
+            inode: Some(7),
+            dev: Some(dev),
+            nlink: Some(2),
+            ..file(path, 100)
+        };
+        let entries = vec![
+            linked("/data/a.bin", 2049),
+            linked("/data/mnt/b.bin", 2050),
+            linked("/data/mnt/c.bin", 2050),
+        ];
+        let usage = summarize(Path::new("/data"), entries, 0);
+        assert_eq!((usage.bytes, usage.files), (200, 2));
+    }
+
+    #[test]
+    fn test_summarize_empty() {
+        let usage = summarize(Path::new("/data"), Vec::new(), 5);
+        assert_eq!(usage, node(Path::new("/data")).usage);
+    }
+}
@@ -0,0 +1,442 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    fmt,
+    path::{Component, Path, PathBuf},
+};
+
+use color_eyre::{Report, Result};
+
+use crate::tx_ssh::{FileInfo, RemoteFileOperations};
+
+/// Where a walk gets its directory listings from
+pub trait DirSource {
+    /// Entries of `dir` with full paths, `.` and `..` excluded
+    fn list(&self, dir: &Path) -> Result<Vec<FileInfo>>;
+    /// Whether `path` is a directory, following symlinks
+    fn is_dir(&self, path: &Path) -> Result<bool>;
+    /// Absolute form of `path` with every symlink resolved
+    fn realpath(&self, path: &Path) -> Result<PathBuf>;
+}
+
+impl<S: DirSource + ?Sized> DirSource for &S {
+    fn list(&self, dir: &Path) -> Result<Vec<FileInfo>> {
+        (**self).list(dir)
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        (**self).is_dir(path)
+    }
+
+    fn realpath(&self, path: &Path) -> Result<PathBuf> {
+        (**self).realpath(path)
+    }
+}
+
+impl DirSource for RemoteFileOperations<'_> {
+    fn list(&self, dir: &Path) -> Result<Vec<FileInfo>> {
+        self.list_directory(dir)
+    }
+
+    fn is_dir(&self, path: &Path) -> Result<bool> {
+        self.is_directory(path)
+    }
+
+    fn realpath(&self, path: &Path) -> Result<PathBuf> {
+        RemoteFileOperations::realpath(self, path)
+    }
+}
+
+/// An entry found by [`RemoteWalk`]
+#[derive(Debug, Clone)]
+pub struct WalkEntry {
+    pub info: FileInfo,
+    /// 1 for children of the root, 2 for their children and so on
+    pub depth: usize,
+}
+
+/// A directory or symlink the walk could not descend into. The walk carries on
+/// with the rest of the tree.
+#[derive(Debug)]
+pub struct WalkError {
+    pub path: PathBuf,
+    pub depth: usize,
+    pub error: Report,
+}
+
+impl fmt::Display for WalkError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {:#}", self.path.display(), self.error)
+    }
+}
+
+impl Error for WalkError {}
+
+/// Depth-first walk of a remote tree, in the spirit of `WalkDir`.
+///
+/// Directories are listed one at a time as the iterator is advanced, so entries
+/// stream out as they are found and a consumer can stop at any point. The root
+/// itself is not yielded.
+pub struct RemoteWalk<S> {
+    source: S,
+    max_depth: Option<usize>,
+    max_entries: Option<usize>,
+    follow_links: bool,
+    /// Directories still to list, with the real paths of the directories above them
+    /// for loop detection
+    stack: Vec<PendingDir>,
+    ready: VecDeque<Result<WalkEntry, WalkError>>,
+    yielded: usize,
+    truncated: bool,
+}
+
+struct PendingDir {
+    path: PathBuf,
+    depth: usize,
+    /// Real paths from the root down to this directory, empty for the root until it
+    /// is listed
+    ancestors: Vec<PathBuf>,
+}
+
+impl<S: DirSource> RemoteWalk<S> {
+    pub fn new(source: S, root: impl AsRef<Path>) -> Self {
+        let root = root.as_ref().to_path_buf();
+        Self {
+            source,
+            max_depth: None,
+            max_entries: None,
+            follow_links: false,
+            stack: vec![PendingDir {
+                path: root,
+                depth: 0,
+                ancestors: Vec::new(),
+            }],
+            ready: VecDeque::new(),
+            yielded: 0,
+            truncated: false,
+        }
+    }
+
+    /// Don't yield entries deeper than `depth`, 1 lists the root only
+    pub fn max_depth(mut self, depth: usize) -> Self {
+        self.max_depth = Some(depth);
+        self
+    }
+
+    /// Stop after this many entries, see [`RemoteWalk::truncated`]
+    pub fn max_entries(mut self, entries: usize) -> Self {
+        self.max_entries = Some(entries);
+        self
+    }
+
+    /// Descend into symlinks to directories. Links back to a directory above them
+    /// are reported as errors instead of followed.
+    pub fn follow_links(mut self, follow: bool) -> Self {
+        self.follow_links = follow;
+        self
+    }
+
+    /// Whether the walk stopped at the entry limit with more of the tree unseen
+    pub fn truncated(&self) -> bool {
+        self.truncated
+    }
+
+    /// List the next directory, queueing its entries and subdirectories
+    fn expand(&mut self, mut dir: PendingDir) {
+        if dir.ancestors.is_empty() {
+            // only links can lead back up, so the host is only asked when following them
+            let real = match self.follow_links {
+                true => self.source.realpath(&dir.path).ok(),
+                false => None,
+            };
+            dir.ancestors
+                .push(real.unwrap_or_else(|| normalize(&dir.path)));
+        }
+        let entries = match self.source.list(&dir.path) {
+            Ok(entries) => entries,
+            Err(error) => {
+                self.ready.push_back(Err(WalkError {
+                    path: dir.path,
+                    depth: dir.depth,
+                    error,
+                }));
+                return;
+            }
+        };
+
+        let depth = dir.depth + 1;
+        let descend = self.max_depth.is_none_or(|max| depth < max);
+        let mut subdirs = Vec::new();
+        for info in entries {
+            if descend && let Some(next) = self.subdir(&dir, &info, depth) {
+                match next {
+                    Ok(next) => subdirs.push(next),
+                    Err(error) => self.ready.push_back(Err(error)),
+                }
+            }
+            self.ready.push_back(Ok(WalkEntry { info, depth }));
+        }
+        // reversed so the stack pops them in listing order
+        self.stack.extend(subdirs.into_iter().rev());
+    }
+
+    /// The directory to descend into for `info`, if any
+    fn subdir(
+        &self,
+        parent: &PendingDir,
+        info: &FileInfo,
+        depth: usize,
+    ) -> Option<Result<PendingDir, WalkError>> {
+        let path = info.raw_path.clone();
+        // compared by where things really are, as a link's target is relative to the
+        // directory it is in rather than the path it was reached through
+        let resolved = if info.is_directory {
+            parent.ancestors.last()?.join(path.file_name()?)
+        } else if info.is_symlink && self.follow_links {
+            match self.source.is_dir(&path) {
+                Ok(true) => {}
+                Ok(false) => return None,
+                Err(error) => return Some(Err(WalkError { path, depth, error })),
+            }
+            match self.source.realpath(&path) {
+                Ok(real) => real,
+                Err(error) => return Some(Err(WalkError { path, depth, error })),
+            }
+        } else {
+            return None;
+        };
+
+        if parent.ancestors.contains(&resolved) {
+            let error = Report::msg(format!("symlink loop to {}", resolved.display()));
+            return Some(Err(WalkError { path, depth, error }));
+        }
+        let mut ancestors = parent.ancestors.clone();
+        ancestors.push(resolved);
+        Some(Ok(PendingDir {
+            path,
+            depth,
+            ancestors,
+        }))
+    }
+}
+
+impl<S: DirSource> Iterator for RemoteWalk<S> {
+    type Item = Result<WalkEntry, WalkError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.max_entries.is_some_and(|max| self.yielded >= max) {
+            self.truncated |= !self.ready.is_empty() || !self.stack.is_empty();
+            self.ready.clear();
+            self.stack.clear();
+            return None;
+        }
+        loop {
+            if let Some(item) = self.ready.pop_front() {
+                self.yielded += item.is_ok() as usize;
+                return Some(item);
+            }
+            let dir = self.stack.pop()?;
+            self.expand(dir);
+        }
+    }
+}
+
+/// Resolve `.` and `..` without asking the host, for a root whose real path is
+/// unknown
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            other => normalized.push(other),
+        }
+    }
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    /// A tree held in memory, keyed by directory
+    #[derive(Default)]
+    struct FakeTree {
+        dirs: HashMap<PathBuf, Vec<FileInfo>>,
+        /// List symlinks without their targets, as SFTP `readdir` does
+        hide_targets: bool,
+    }
+
+    impl FakeTree {
+        fn add(&mut self, dir: &str, name: &str, kind: char, target: Option<&str>) {
+            let path = Path::new(dir).join(name);
+            self.dirs
+                .entry(PathBuf::from(dir))
+                .or_default()
+                .push(FileInfo {
+                    name: name.to_string(),
+                    path: path.to_string_lossy().into_owned(),
+                    raw_path: path.clone(),
+                    is_directory: kind == 'd',
+                    is_symlink: kind == 'l',
+                    symlink_target: target.map(PathBuf::from),
+                    ..Default::default()
+                });
+            if kind == 'd' {
+                self.dirs.entry(path).or_default();
+            }
+        }
+    }
+
+    impl FakeTree {
+        /// Replace every symlink along `path` with its target
+        fn resolve(&self, path: &Path) -> PathBuf {
+            let mut resolved = PathBuf::new();
+            for component in path.components() {
+                resolved.push(component);
+                let target = resolved
+                    .parent()
+                    .and_then(|parent| self.dirs.get(parent))
+                    .and_then(|entries| entries.iter().find(|info| info.raw_path == resolved))
+                    .and_then(|info| info.symlink_target.clone());
+                if let Some(target) = target {
+                    resolved = self.resolve(&normalize(&resolved.parent().unwrap().join(target)));
+                }
+            }
+            resolved
+        }
+    }
+
+    impl DirSource for FakeTree {
+        fn list(&self, dir: &Path) -> Result<Vec<FileInfo>> {
+            let entries = self
+                .dirs
+                .get(&self.resolve(dir))
+                .ok_or_else(|| Report::msg("Permission denied"))?;
+            Ok(entries
+                .iter()
+                .map(|info| {
+                    let path = dir.join(&info.name);
+                    let symlink_target = match self.hide_targets {
+                        true => None,
+                        false => info.symlink_target.clone(),
+                    };
+                    FileInfo {
+                        path: path.to_string_lossy().into_owned(),
+                        raw_path: path,
+                        symlink_target,
+                        ..info.clone()
+                    }
+                })
+                .collect())
+        }
+
+        fn is_dir(&self, path: &Path) -> Result<bool> {
+            Ok(self.dirs.contains_key(&self.resolve(path)))
+        }
+
+        fn realpath(&self, path: &Path) -> Result<PathBuf> {
+            Ok(self.resolve(path))
+        }
+    }
+
+    fn tree() -> FakeTree {
+        let mut tree = FakeTree::default();
+        tree.add("/data", "a.txt", 'f', None);
+        tree.add("/data", "runs", 'd', None);
+        tree.add("/data", "locked", 'd', None);
+        tree.add("/data/runs", "run_1", 'd', None);
+        tree.add("/data/runs/run_1", "out.bin", 'f', None);
+        tree.add("/data/runs", "latest", 'l', Some("run_1"));
+        tree.add("/data/runs", "up", 'l', Some(".."));
+        tree.dirs.remove(Path::new("/data/locked"));
+        tree
+    }
+
+    fn names(walk: impl Iterator<Item = Result<WalkEntry, WalkError>>) -> Vec<String> {
+        walk.map(|item| match item {
+            Ok(entry) => format!("{}:{}", entry.depth, entry.info.path),
+            Err(error) => format!("error {}", error.path.display()),
+        })
+        .collect()
+    }
+
+    #[test]
+    fn test_walk_reports_errors_and_continues() {
+        let tree = tree();
+        assert_eq!(
+            names(RemoteWalk::new(&tree, "/data")),
+            vec![
+                "1:/data/a.txt",
+                "1:/data/runs",
+                "1:/data/locked",
+                "2:/data/runs/run_1",
+                "2:/data/runs/latest",
+                "2:/data/runs/up",
+                "3:/data/runs/run_1/out.bin",
+                "error /data/locked",
+            ]
+        );
+    }
+
+    #[test]
+    fn test_walk_follows_links_but_not_loops() {
+        let tree = tree();
+        let walk = RemoteWalk::new(&tree, "/data").follow_links(true);
+        let names = names(walk);
+        assert!(names.contains(&String::from("3:/data/runs/latest/out.bin")));
+        assert!(names.contains(&String::from("error /data/runs/up")));
+    }
+
+    #[test]
+    fn test_walk_resolves_links_by_real_path() {
+        let mut tree = tree();
+        tree.add("/data", "abs", 'l', Some("/data/runs/run_1"));
+        tree.add("/data/runs/run_1", "parent", 'l', Some(".."));
+        tree.add("/data/runs/run_1", "again", 'l', Some("../latest"));
+        let walk = RemoteWalk::new(&tree, "/data")
+            .follow_links(true)
+            .max_entries(1000);
+        let names = names(walk);
+        assert!(names.len() < 1000, "{names:?}");
+
+        // `..` of the real run_1 is runs, not the /data that /data/abs sits in
+        assert!(names.contains(&String::from("3:/data/abs/parent/run_1")));
+        assert!(names.contains(&String::from("error /data/abs/parent/run_1")));
+        // reached through another link, but still run_1
+        assert!(names.contains(&String::from("error /data/runs/run_1/again")));
+        assert!(names.contains(&String::from("error /data/abs/again")));
+    }
+
+    #[test]
+    fn test_walk_reads_missing_link_targets() {
+        let tree = FakeTree {
+            hide_targets: true,
+            ..tree()
+        };
+        let names = names(RemoteWalk::new(&tree, "/data").follow_links(true));
+        assert!(names.contains(&String::from("3:/data/runs/latest/out.bin")));
+        assert!(names.contains(&String::from("error /data/runs/up")));
+    }
+
+    #[test]
+    fn test_walk_limits() {
+        let tree = tree();
+        let shallow = names(RemoteWalk::new(&tree, "/data").max_depth(1));
+        assert_eq!(
+            shallow,
+            vec!["1:/data/a.txt", "1:/data/runs", "1:/data/locked"]
+        );
+
+        let mut walk = RemoteWalk::new(&tree, "/data").max_entries(2);
+        assert_eq!(walk.by_ref().count(), 2);
+        assert!(walk.truncated());
+
+        let mut walk = RemoteWalk::new(&tree, "/data/runs/run_1").max_entries(1);
+        assert_eq!(walk.by_ref().count(), 1);
+        assert!(!walk.truncated());
+    }
+}